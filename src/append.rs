@@ -0,0 +1,205 @@
+// Adding entries to the worklog as they happen, for the `clock`, `note`
+// and `expense` commands.
+//
+// Entries go at the end of today's day, after its last non-blank line.
+// Without a heading for today one is added before the newest day if the
+// worklog runs newest first, or after it if oldest first. The time comes
+// from a `TimeSource` so that tests can fix it.
+
+use std::collections::BTreeMap;
+use chrono::{Local, NaiveDate, NaiveDateTime, Timelike};
+
+use diagnostics::Diagnostics;
+use formatter::format_time;
+use model::{DayOrder, EntryKind, Time};
+use parser::{describe, line_to_raw_entry, parse_sources, ParseOptions, RawEntry};
+use sources::Sources;
+use errors::*;
+
+pub trait TimeSource {
+    // The wall-clock time, as it would be written in the worklog
+    fn now(&self) -> NaiveDateTime;
+}
+
+pub struct SystemTime;
+
+impl TimeSource for SystemTime {
+    fn now(&self) -> NaiveDateTime {
+        Local::now().naive_local()
+    }
+}
+
+pub struct FixedTime(pub NaiveDateTime);
+
+impl TimeSource for FixedTime {
+    fn now(&self) -> NaiveDateTime {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NewEntry {
+    ClockIn(Option<String>),
+    // Without a company, the only open session is clocked out
+    ClockOut(Option<String>),
+    Note(String),
+    Expense(f64, String),
+}
+
+// A file with entries added
+#[derive(Debug, Clone, PartialEq)]
+pub struct Addition {
+    pub path: String,
+    pub text: String,
+    // The lines added, without the heading if one was
+    pub lines: Vec<String>,
+}
+
+pub fn add_entry<T: TimeSource>(sources: &Sources, opts: &ParseOptions, entry: &NewEntry,
+                                clock: &T) -> Result<Addition> {
+    let (worklog, _) = parse_sources(sources, opts);
+    let now = clock.now();
+    let today = now.date();
+    let time = format_time(Time::new(now.hour() as u8, now.minute() as u8), opts);
+
+    // Clocked in, by company, with the line of the clock-in
+    let open: BTreeMap<Option<String>, usize> = worklog.open_sessions.iter()
+        .map(|s| (s.company.clone(), s.line))
+        .collect();
+
+    let lines = match *entry {
+        NewEntry::ClockIn(ref company) => {
+            if let Some(&line) = open.get(company) {
+                bail!("already clocked in {}at {}", describe(company), sources.describe(line));
+            }
+            vec![format!("- clock in{}", suffix(company)), format!("- {}", time)]
+        }
+        NewEntry::ClockOut(ref company) => {
+            let company = match *company {
+                Some(ref c) if !open.contains_key(company) => bail!("not clocked in for '{}'", c),
+                Some(_) => company.clone(),
+                None if open.is_empty() => bail!("not clocked in"),
+                // Name the company when there's only one
+                None if open.len() == 1 => open.keys().next().cloned().unwrap_or(None),
+                None => {
+                    let names: Vec<&str> = open.keys().map(|c| c.as_deref().unwrap_or("no company")).collect();
+                    bail!("clocked in for {}; name the company", names.join(", "));
+                }
+            };
+            vec![format!("- {}", time), format!("- clock out{}", suffix(&company))]
+        }
+        NewEntry::Note(ref text) => {
+            if text.trim().is_empty() {
+                bail!("the note is empty");
+            }
+            let line = one_line(format!("- {}", text.trim()))?;
+            match parse_line(&line, opts) {
+                Some(EntryKind::Action(..)) => vec![line],
+                _ => bail!("'{}' would not be read as a note", text.trim()),
+            }
+        }
+        NewEntry::Expense(cost, ref what) => {
+            if !cost.is_finite() || cost < 0.0 {
+                bail!("an expense must be a cost of 0 or more, not {}", cost);
+            }
+            let line = one_line(format!("- Expense: ${:.2}, {}", cost, what.trim()))?;
+            match parse_line(&line, opts) {
+                Some(EntryKind::Expense(..)) => vec![line],
+                _ => bail!("'{}' would not be read as an expense", what.trim()),
+            }
+        }
+    };
+
+    Ok(insert(sources, &worklog, today, lines))
+}
+
+fn insert(sources: &Sources, worklog: &::model::Worklog, today: NaiveDate, lines: Vec<String>) -> Addition {
+    let all = sources.lines();
+    // The line after the last non-blank one before `end`
+    let after_text = |start: usize, end: usize| {
+        (start .. end).rev().find(|&l| !all[l - 1].trim().is_empty()).map_or(start, |l| l + 1)
+    };
+
+    let (at, new_day) = match worklog.days.iter().rev().find(|d| d.date == today) {
+        Some(day) => (after_text(day.line, day.end_line), false),
+        None => match worklog.days.last() {
+            Some(newest) if worklog.order == DayOrder::NewestFirst => (newest.line, true),
+            Some(newest) => (after_text(newest.line, newest.end_line), true),
+            // An empty worklog, or one with no days yet
+            None => (after_text(1, all.len() + 1), true),
+        },
+    };
+
+    let before_newest = new_day && worklog.order == DayOrder::NewestFirst && !worklog.days.is_empty();
+    // Placed after the line before, which keeps to that line's file
+    let (file, local) = if before_newest {
+        sources.locate(at)
+    } else if at > 1 {
+        let (file, local) = sources.locate(at - 1);
+        (file, local + 1)
+    } else {
+        (&sources.files()[0], 1)
+    };
+
+    let mut text: Vec<&str> = file.text.lines().collect();
+    let mut added = vec![];
+    if new_day {
+        // Days set apart by blank lines get one too
+        let mut headings: Vec<usize> = worklog.days.iter().map(|d| d.line).collect();
+        headings.sort();
+        let spaced = headings.windows(2).any(|w| {
+            sources.locate(w[0]).0.path == sources.locate(w[1]).0.path && all[w[1] - 2].trim().is_empty()
+        });
+        let heading = format!("# {}", today.format("%Y-%m-%d"));
+        let before_heading = local > 1 && !text[local - 2].trim().is_empty();
+        if before_newest {
+            added.push(heading);
+            added.extend(lines.iter().cloned());
+            if spaced {
+                added.push(String::new());
+            }
+        } else {
+            if spaced && before_heading {
+                added.push(String::new());
+            }
+            added.push(heading);
+            added.extend(lines.iter().cloned());
+        }
+    } else {
+        added.extend(lines.iter().cloned());
+    }
+    let index = (local - 1).min(text.len());
+    for (i, line) in added.iter().enumerate() {
+        text.insert(index + i, line);
+    }
+
+    let newline = if file.text.contains("\r\n") { "\r\n" } else { "\n" };
+    let mut out = text.join(newline);
+    out.push_str(newline);
+    Addition { path: file.path.clone(), text: out, lines }
+}
+
+fn one_line(line: String) -> Result<String> {
+    if line.contains('\n') || line.contains('\r') {
+        bail!("an entry must be one line");
+    }
+    Ok(line)
+}
+
+// How the parser reads a line added, to refuse text that would be taken
+// for a clock line, a heading or anything else than intended
+fn parse_line(line: &str, opts: &ParseOptions) -> Option<EntryKind> {
+    let mut diags = Diagnostics::new();
+    match line_to_raw_entry(line, opts, &mut diags.line("", 0, line)) {
+        RawEntry::Entry(kind) => Some(kind),
+        _ => None,
+    }
+}
+
+// " (Nervos)"
+fn suffix(company: &Option<String>) -> String {
+    match *company {
+        Some(ref c) => format!(" ({})", c),
+        None => String::new(),
+    }
+}
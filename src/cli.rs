@@ -0,0 +1,274 @@
+// Command-line arguments
+
+use chrono::{Datelike, NaiveDate};
+use chrono_tz::Tz;
+use clap::{Parser, Subcommand};
+
+use worklog_scan::model::{DayOrder, Tag};
+use worklog_scan::time_reporting::{Midnight, RoundingMode, RoundingScope, TagDisplay};
+
+#[derive(Debug, Parser)]
+#[command(name = "worklog-scan", version, about = "Reports on a markdown worklog")]
+pub struct Args {
+    /// The worklog to read: a file, a directory of .md files or a glob;
+    /// repeat for a worklog split across several
+    #[arg(short, long, global = true)]
+    pub file: Vec<String>,
+
+    /// The config file, instead of worklog-scan.toml next to the worklog
+    /// or ~/.config/worklog-scan/config.toml
+    #[arg(long, global = true)]
+    pub config: Option<String>,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Print an invoice for the time clocked to a project
+    #[command(alias = "tr")]
+    Invoice(Box<InvoiceArgs>),
+    /// Write an invoice for every company clocked in the period
+    Batch(BatchArgs),
+    /// Analyze pleasure/pain predictions
+    Pp(PpArgs),
+    /// Check the whole worklog for problems, exiting nonzero on errors
+    Lint(LintArgs),
+    /// Check that issued invoices still match the worklog, exiting nonzero if not
+    Verify(VerifyArgs),
+    /// Rewrite the worklog in canonical form, printing it unless --check or --in-place
+    Fmt(FmtArgs),
+    /// Clock in or out now, under today's heading
+    Clock(ClockArgs),
+    /// Log an action now, under today's heading
+    Note(NoteArgs),
+    /// Log an expense under today's heading
+    Expense(ExpenseArgs),
+    /// Show open sessions and the time worked today and this week
+    Status(StatusArgs),
+}
+
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct PeriodArgs {
+    /// Report on a whole month (YYYY-MM)
+    #[arg(long, value_parser = parse_month, conflicts_with_all = ["from", "to"])]
+    pub month: Option<(NaiveDate, NaiveDate)>,
+    /// First day of the reporting period (YYYY-MM-DD)
+    #[arg(long, value_parser = parse_date, requires = "to")]
+    pub from: Option<NaiveDate>,
+    /// Last day of the reporting period, inclusive (YYYY-MM-DD)
+    #[arg(long, value_parser = parse_date, requires = "from")]
+    pub to: Option<NaiveDate>,
+}
+
+#[derive(Debug, Default, clap::Args)]
+pub struct InvoiceArgs {
+    #[command(flatten)]
+    pub period: PeriodArgs,
+    /// Hourly rate, overriding the client profile
+    #[arg(long)]
+    pub rate: Option<f64>,
+    /// Your name, as the invoice is from, overriding the config file
+    #[arg(long)]
+    pub name: Option<String>,
+    /// The company named in clock-in lines, e.g. "Nervos" for "clock in (Nervos)"
+    #[arg(long)]
+    pub project: Option<String>,
+    /// The client profile to use, keyed by the company in clock-in lines.
+    /// Without a profile, the name to bill, if different from the project.
+    #[arg(long)]
+    pub client: Option<String>,
+    /// Invoice number, instead of the next one from the ledger
+    #[arg(long)]
+    pub invoice_no: Option<String>,
+    /// Issue date (YYYY-MM-DD)
+    #[arg(long, value_parser = parse_date)]
+    pub issued: Option<NaiveDate>,
+    /// Due date (YYYY-MM-DD)
+    #[arg(long, value_parser = parse_date)]
+    pub due: Option<NaiveDate>,
+    /// Output format: normal, ds, json, csv, or the name of a template in the
+    /// templates directory; overrides the client profile
+    #[arg(long)]
+    pub format: Option<String>,
+    /// Round billed time to this many minutes, 0 for exact, overriding
+    /// the client profile
+    #[arg(long)]
+    pub round_increment: Option<u32>,
+    /// Round to the nearest increment, or up or down
+    #[arg(long, value_parser = str::parse::<RoundingMode>)]
+    pub round_mode: Option<RoundingMode>,
+    /// Round each session, day or invoice
+    #[arg(long, value_parser = str::parse::<RoundingScope>)]
+    pub round_per: Option<RoundingScope>,
+    /// Minutes billed at least for each session, day or invoice
+    #[arg(long)]
+    pub round_minimum: Option<u32>,
+    /// Bill sessions running past midnight on the day they start, or
+    /// split them at midnight; overrides the client profile
+    #[arg(long, value_parser = str::parse::<Midnight>)]
+    pub midnight: Option<Midnight>,
+    /// Show times in this zone, e.g. America/New_York, overriding the
+    /// client profile
+    #[arg(long)]
+    pub timezone: Option<Tz>,
+    /// List breaks taken within sessions on the invoice
+    #[arg(long)]
+    pub show_breaks: bool,
+    /// Only bill work tagged with this, e.g. review or issue:1234
+    #[arg(long, value_parser = str::parse::<Tag>)]
+    pub tag: Option<Tag>,
+    /// Keep tags in the actions on the invoice, or strip them;
+    /// overrides the client profile
+    #[arg(long, value_parser = str::parse::<TagDisplay>)]
+    pub tags: Option<TagDisplay>,
+    /// List days oldest or newest first; oldest by default
+    #[arg(long, value_parser = str::parse::<DayOrder>)]
+    pub order: Option<DayOrder>,
+    /// Directory of invoice templates, named <format>.html, overriding
+    /// the config file
+    #[arg(long)]
+    pub templates: Option<String>,
+    /// The invoice summary, overriding the client profile
+    #[arg(long)]
+    pub notes: Option<String>,
+    /// Write the invoice to this file instead of stdout
+    #[arg(short, long)]
+    pub output: Option<String>,
+    /// Also write the expenses to this file as CSV
+    #[arg(long)]
+    pub expenses_output: Option<String>,
+    /// Field delimiter for CSV output: a single character, or "tab"
+    #[arg(long, value_parser = parse_delimiter, default_value = ",")]
+    pub delimiter: u8,
+    /// Print the invoice without recording it in the ledger
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+// Each invoice is made as if by `invoice --client <company>`
+#[derive(Debug, clap::Args)]
+pub struct BatchArgs {
+    #[command(flatten)]
+    pub period: PeriodArgs,
+    /// Your name, as the invoices are from, overriding the config file
+    #[arg(long)]
+    pub name: Option<String>,
+    /// Issue date (YYYY-MM-DD)
+    #[arg(long, value_parser = parse_date)]
+    pub issued: Option<NaiveDate>,
+    /// Due date (YYYY-MM-DD)
+    #[arg(long, value_parser = parse_date)]
+    pub due: Option<NaiveDate>,
+    /// Output format for every invoice, overriding the client profiles
+    #[arg(long)]
+    pub format: Option<String>,
+    /// Directory of invoice templates, overriding the config file
+    #[arg(long)]
+    pub templates: Option<String>,
+    /// Field delimiter for CSV output: a single character, or "tab"
+    #[arg(long, value_parser = parse_delimiter, default_value = ",")]
+    pub delimiter: u8,
+    /// Directory to write the invoices to
+    #[arg(long, default_value = ".")]
+    pub out_dir: String,
+    /// Write the invoices without recording them in the ledger
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct LintArgs {
+    /// A company that may appear in clock lines; can be repeated.
+    /// Without any, only likely misspellings are reported.
+    #[arg(long = "company")]
+    pub companies: Vec<String>,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct PpArgs {
+    /// List weeks oldest or newest first
+    #[arg(long, value_parser = str::parse::<DayOrder>, default_value = "oldest")]
+    pub order: DayOrder,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct VerifyArgs {
+    /// Only check this invoice number; can be repeated
+    #[arg(long = "invoice")]
+    pub invoices: Vec<String>,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct FmtArgs {
+    /// List the lines that would change, exiting nonzero if any would
+    #[arg(long, conflicts_with = "in_place")]
+    pub check: bool,
+    /// Rewrite the files, keeping the old ones with a .bak suffix
+    #[arg(long, short = 'i')]
+    pub in_place: bool,
+    /// Name the weekday in every heading
+    #[arg(long)]
+    pub weekdays: bool,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct ClockArgs {
+    #[arg(value_enum)]
+    pub direction: ClockDirection,
+    /// The company; clocking out without one ends the only open session
+    pub company: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ClockDirection {
+    In,
+    Out,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct NoteArgs {
+    pub text: String,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct ExpenseArgs {
+    /// In dollars, e.g. 12.50
+    pub cost: f64,
+    pub what: String,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct StatusArgs {
+    /// One line, for a shell prompt or status bar
+    #[arg(long)]
+    pub short: bool,
+}
+
+pub fn parse_date(s: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map_err(|e| format!("expected YYYY-MM-DD: {}", e))
+}
+
+pub fn parse_delimiter(s: &str) -> Result<u8, String> {
+    match s {
+        "tab" | "\\t" => Ok(b'\t'),
+        _ if s.len() == 1 && s.is_ascii() => Ok(s.as_bytes()[0]),
+        _ => Err("expected a single ASCII character, or \"tab\"".to_string()),
+    }
+}
+
+// The first and last days of a YYYY-MM month
+pub fn parse_month(s: &str) -> Result<(NaiveDate, NaiveDate), String> {
+    let first = NaiveDate::parse_from_str(&format!("{}-01", s), "%Y-%m-%d")
+        .map_err(|e| format!("expected YYYY-MM: {}", e))?;
+    let (year, month) = if first.month() == 12 {
+        (first.year() + 1, 1)
+    } else {
+        (first.year(), first.month() + 1)
+    };
+    let last = NaiveDate::from_ymd_opt(year, month, 1).and_then(|d| d.pred_opt())
+        .ok_or_else(|| format!("month out of range: {}", s))?;
+    Ok((first, last))
+}
@@ -0,0 +1,205 @@
+// The configuration file: who is invoicing, and how to bill each
+// client.
+//
+//     worklog = "~/brson.github.com/worklog.md" # or a directory or
+//                                 # glob, for one split across files
+//     ledger = "invoices.toml"    # defaults to worklog-scan-ledger.toml
+//                                 # next to the worklog
+//     templates = "templates"     # invoice templates, <format>.html
+//     max_session_hours = 16      # longer sessions are errors
+//     time_formats = ["12h", "24h"] # timestamps like 9:30 PM or 21:30;
+//                                 # only 12h by default
+//     timezone = "Europe/Berlin"  # the worklog's zone before any
+//                                 # `tz: Area/City` line
+//
+//     [numbering]
+//     pattern = "{client}-{year}-{seq:03}"
+//     per_client = true           # a sequence for each client
+//     yearly = true               # restarting every year
+//
+//     [identity]
+//     name = "Common Orbit LLC"
+//     email = "andersrb@gmail.com"
+//     address = "..."
+//     tax_id = "..."
+//     bank = "..."
+//
+//     [clients.Nervos]            # as in "clock in (Nervos)"
+//     name = "Decrypted Sapiens"  # who to bill, if not "Nervos"
+//     rate = 200
+//     currency = "USD"
+//     style = "ds"
+//     payment_terms = 18          # days from issue to due date
+//     address = "..."
+//     notes = "..."               # the invoice summary
+//     midnight = "split"          # bill sessions past midnight on the
+//                                 # day they start (default) or split
+//     timezone = "America/New_York" # show times in the client's zone
+//     show_breaks = true          # list breaks taken within sessions
+//     tags = "strip"              # keep (default) or strip #tags and
+//                                 # key:value tags in actions
+//
+//     [clients.Nervos.rounding]   # how clocked time is billed
+//     increment = 15              # minutes; 0 for exact, default 30
+//     mode = "up"                 # nearest (default), up or down
+//     per = "day"                 # session (default), day or invoice
+//     minimum = 60                # minutes billed at least, default 0
+//
+// It is looked for next to the worklog as `worklog-scan.toml`, then in
+// `$XDG_CONFIG_HOME/worklog-scan/config.toml`, which defaults to
+// `~/.config/worklog-scan/config.toml`.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use chrono_tz::Tz;
+use toml;
+
+use errors::*;
+use parser::{ParseOptions, TimeFormat};
+use sources;
+use time_reporting::{Midnight, Rounding, TagDisplay};
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    // Default worklog, used when none is given on the command line
+    pub worklog: Option<String>,
+    // The ledger of issued invoices
+    pub ledger: Option<String>,
+    // Directory of invoice templates, overriding the built-in ones
+    pub templates: Option<String>,
+    // Sessions longer than this are probably missing a clock-out
+    pub max_session_hours: Option<f64>,
+    pub time_formats: Option<Vec<TimeFormat>>,
+    pub timezone: Option<Tz>,
+    #[serde(default)]
+    pub numbering: Numbering,
+    #[serde(default)]
+    pub identity: Identity,
+    // Keyed by the company name used in clock-in lines
+    #[serde(default)]
+    pub clients: BTreeMap<String, ClientProfile>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Identity {
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub address: Option<String>,
+    pub tax_id: Option<String>,
+    pub bank: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientProfile {
+    pub name: Option<String>,
+    pub rate: Option<f64>,
+    pub currency: Option<String>,
+    pub style: Option<String>,
+    pub payment_terms: Option<u32>,
+    pub address: Option<String>,
+    pub notes: Option<String>,
+    pub rounding: Option<Rounding>,
+    pub midnight: Option<Midnight>,
+    pub timezone: Option<Tz>,
+    pub show_breaks: Option<bool>,
+    pub tags: Option<TagDisplay>,
+}
+
+// How invoice numbers are allocated; see `ledger::format_number`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Numbering {
+    pub pattern: String,
+    pub per_client: bool,
+    pub yearly: bool,
+}
+
+impl Default for Numbering {
+    fn default() -> Numbering {
+        Numbering {
+            pattern: "{seq}".to_string(),
+            per_client: false,
+            yearly: false,
+        }
+    }
+}
+
+pub static CONFIG_FILE_NAME: &str = "worklog-scan.toml";
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config> {
+        let mut text = String::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut text))
+            .chain_err(|| format!("reading {}", path.display()))?;
+        let mut config: Config = toml::from_str(&text)
+            .chain_err(|| format!("parsing {}", path.display()))?;
+
+        // Paths are relative to the config file
+        let resolve = |p: String| {
+            let p = expand_home(&p);
+            let p = match path.parent() {
+                Some(dir) => dir.join(p),
+                None => p,
+            };
+            p.to_string_lossy().into_owned()
+        };
+        config.worklog = config.worklog.take().map(&resolve);
+        config.ledger = config.ledger.take().map(&resolve);
+        config.templates = config.templates.take().map(&resolve);
+
+        Ok(config)
+    }
+
+    // Find and load the config for a worklog, or for no particular
+    // worklog. A missing config file is not an error.
+    pub fn find(worklog: Option<&Path>) -> Result<Option<(PathBuf, Config)>> {
+        let beside = worklog.map(|w| sources::directory(w).join(CONFIG_FILE_NAME));
+
+        let candidates = beside.into_iter().chain(user_config_path());
+        for path in candidates {
+            if path.is_file() {
+                let config = Config::load(&path)?;
+                return Ok(Some((path, config)));
+            }
+        }
+
+        Ok(None)
+    }
+
+    pub fn client(&self, company: &str) -> Option<&ClientProfile> {
+        self.clients.get(company)
+    }
+
+    pub fn parse_options(&self) -> ParseOptions {
+        let mut opts = ParseOptions::default();
+        if let Some(ref formats) = self.time_formats {
+            opts.time_formats = formats.clone();
+        }
+        opts.timezone = self.timezone;
+        opts
+    }
+}
+
+fn user_config_path() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")));
+    dir.map(|d| d.join("worklog-scan").join("config.toml"))
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    if let Some(rest) = path.strip_prefix("~/") {
+        if let Some(home) = env::var_os("HOME") {
+            return PathBuf::from(home).join(rest);
+        }
+    }
+    PathBuf::from(path)
+}
@@ -0,0 +1,165 @@
+// Problems found while reading a worklog.
+//
+// Parsing never stops at the first bad line. Instead every problem is
+// recorded here, with enough location information to print it the way
+// rustc does, quoting the offending line:
+//
+//     error: use 'clock in', not 'clockin'
+//       --> worklog.md:12:3
+//        |
+//     12 | - clockin (Nervos)
+//        |   ^^^^^^^
+
+use std::fmt;
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub path: String,
+    pub line: usize, // 1-based
+    pub span: Range<usize>, // byte offsets into `source`
+    pub source: String, // the text of the offending line
+}
+
+impl Diagnostic {
+    // 1-based column of the start of the span, counted in chars
+    pub fn column(&self) -> usize {
+        self.source.get(..self.span.start)
+            .map(|s| s.chars().count())
+            .unwrap_or(0) + 1
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let lineno = self.line.to_string();
+        let gutter = " ".repeat(lineno.len());
+        let caret_pad = " ".repeat(self.column() - 1);
+        let caret_len = self.source.get(self.span.clone())
+            .map(|s| s.chars().count())
+            .unwrap_or(0)
+            .max(1);
+
+        writeln!(f, "{}: {}", self.severity, self.message)?;
+        writeln!(f, "{}--> {}:{}:{}", gutter, self.path, self.line, self.column())?;
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", lineno, self.source)?;
+        writeln!(f, "{} | {}{}", gutter, caret_pad, "^".repeat(caret_len))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Diagnostics {
+    list: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Diagnostics {
+        Diagnostics::default()
+    }
+
+    pub fn push(&mut self, diag: Diagnostic) {
+        self.list.push(diag);
+    }
+
+    // Start reporting problems against a single source line
+    pub fn line<'a>(&'a mut self, path: &'a str, line: usize, source: &'a str) -> LineDiagnostics<'a> {
+        LineDiagnostics { diags: self, path, line, source }
+    }
+
+    pub fn iter(&self) -> ::std::slice::Iter<'_, Diagnostic> {
+        self.list.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    pub fn count(&self, severity: Severity) -> usize {
+        self.list.iter().filter(|d| d.severity == severity).count()
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.list.iter().filter(|d| d.severity == Severity::Error)
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.count(Severity::Error) > 0
+    }
+
+    // Print every diagnostic to stderr in source order, followed by a
+    // one-line tally
+    pub fn emit(&self) {
+        let mut sorted: Vec<&Diagnostic> = self.list.iter().collect();
+        sorted.sort_by(|a, b| (&a.path, a.line).cmp(&(&b.path, b.line)));
+        for diag in sorted {
+            eprintln!("{}", diag);
+        }
+
+        let errors = self.count(Severity::Error);
+        let warnings = self.count(Severity::Warning);
+        if errors > 0 || warnings > 0 {
+            eprintln!("{} error(s), {} warning(s)", errors, warnings);
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a Diagnostics {
+    type Item = &'a Diagnostic;
+    type IntoIter = ::std::slice::Iter<'a, Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.list.iter()
+    }
+}
+
+// A handle for reporting against one line, so the line parsers don't
+// need to know which file or line number they are looking at.
+pub struct LineDiagnostics<'a> {
+    diags: &'a mut Diagnostics,
+    path: &'a str,
+    line: usize,
+    source: &'a str,
+}
+
+impl<'a> LineDiagnostics<'a> {
+    pub fn report(&mut self, severity: Severity, span: Range<usize>, message: String) {
+        self.diags.push(Diagnostic {
+            severity,
+            message,
+            path: self.path.to_string(),
+            line: self.line,
+            span,
+            source: self.source.to_string(),
+        });
+    }
+
+    pub fn error(&mut self, span: Range<usize>, message: String) {
+        self.report(Severity::Error, span, message);
+    }
+
+    pub fn warning(&mut self, span: Range<usize>, message: String) {
+        self.report(Severity::Warning, span, message);
+    }
+}
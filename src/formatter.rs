@@ -0,0 +1,197 @@
+// Rewriting a worklog in canonical form, for the `fmt` command.
+//
+// Each line is classified by `line_to_raw_entry` and rewritten only if
+// it is nothing but what was recognized, so no text is lost:
+//
+//     # 2021-02-03 wed       ->  # 2021-02-03 Wednesday
+//     -   9:05am             ->  - 9:05 AM
+//     - clockin  ( Nervos )  ->  - clock in (Nervos)
+//     - Expense:$12.5,lunch  ->  - Expense: $12.50, lunch
+//     -  did things          ->  - did things
+//
+// Prose and lines that didn't parse are left byte-for-byte, as are
+// timestamps with seconds, which the parser drops. A line is only
+// rewritten if it still parses as the same kind of entry.
+
+use std::mem;
+use chrono::{Datelike, NaiveDate, Weekday};
+use regex::{Captures, Regex};
+
+use diagnostics::Diagnostics;
+use model::{EntryKind, Time};
+use parser::{line_to_raw_entry, ParseOptions, RawEntry, TimeFormat};
+
+#[derive(Debug, Clone, Default)]
+pub struct FormatOptions {
+    // Name the weekday in every heading. Without this, headings keep a
+    // weekday only if they had one.
+    pub weekdays: bool,
+}
+
+pub fn format_worklog(text: &str, parse: &ParseOptions, opts: &FormatOptions) -> String {
+    let newline = if text.contains("\r\n") { "\r\n" } else { "\n" };
+    let mut out: Vec<String> = text.lines().map(|line| {
+        format_line(line, parse, opts).unwrap_or_else(|| line.to_string())
+    }).collect();
+    if text.ends_with('\n') {
+        out.push(String::new());
+    }
+    out.join(newline)
+}
+
+// The canonical form of a line, or `None` to leave it as it is
+pub fn format_line(line: &str, parse: &ParseOptions, opts: &FormatOptions) -> Option<String> {
+    let raw = raw_entry(line, parse);
+    let formatted = canonical(line, &raw, parse, opts)?;
+    let same = match (raw, raw_entry(&formatted, parse)) {
+        (RawEntry::NewDay(a), RawEntry::NewDay(b)) => a == b,
+        (RawEntry::Entry(a), RawEntry::Entry(b)) => mem::discriminant(&a) == mem::discriminant(&b),
+        _ => false,
+    };
+    if same && formatted != line { Some(formatted) } else { None }
+}
+
+// Problems are for `lint` to report
+fn raw_entry(line: &str, parse: &ParseOptions) -> RawEntry {
+    let mut diags = Diagnostics::new();
+    line_to_raw_entry(line, parse, &mut diags.line("", 0, line))
+}
+
+fn canonical(line: &str, raw: &RawEntry, parse: &ParseOptions, opts: &FormatOptions) -> Option<String> {
+    let kind = match *raw {
+        RawEntry::NewDay(date) => return Some(format_heading(line, date, opts)),
+        RawEntry::Include(..) => return None,
+        RawEntry::Entry(ref kind) => kind,
+    };
+
+    let text = match line.strip_prefix("- ") {
+        Some(rest) => rest.trim(),
+        None => "",
+    };
+    let bullet = |s: &str| Some(format!("- {}", s));
+
+    match *kind {
+        EntryKind::Junk(..) => None,
+        EntryKind::Time(time) => {
+            if TIME_ONLY.is_match(text) { bullet(&format_time(time, parse)) } else { bullet(text) }
+        }
+        EntryKind::ClockIn(..) | EntryKind::ClockOut(..) |
+        EntryKind::Pause(..) | EntryKind::Resume(..) => {
+            match CLOCK.captures(text) {
+                Some(caps) => bullet(&format_clock(&caps, parse)),
+                // Only the spelling is fixed
+                None => Some(CLOCK_WORD.replace_all(line, |caps: &Captures| {
+                    format!("{} {}", &caps[1], caps[2].to_ascii_lowercase())
+                }).into_owned()),
+            }
+        }
+        EntryKind::Expense(ref expense) => {
+            match EXPENSE.captures(text) {
+                Some(caps) => {
+                    let cost = if cents(&caps[1]) { format!("{:.2}", expense.cost) } else { caps[1].to_string() };
+                    bullet(&format!("Expense: ${}, {}", cost, expense.what.trim()))
+                }
+                None => bullet(text),
+            }
+        }
+        EntryKind::TimeZone(tz) => {
+            let prefix = if line.starts_with('-') { "- " } else { "" };
+            Some(format!("{}tz: {}", prefix, tz.name()))
+        }
+        EntryKind::Action(..) | EntryKind::Prediction(..) |
+        EntryKind::Duration(..) | EntryKind::Break(..) => bullet(text),
+    }
+}
+
+lazy_static! {
+    // A time without seconds
+    static ref TIME_ONLY: Regex = Regex::new(&format!("^{}$", TIME)).expect("");
+    static ref CLOCK: Regex = Regex::new(&format!(
+        r"^(?:({t}) +)?(?i:(clock) ?(in|out)|(pause|resume))(?: +({t}))? *(\([^)]*\))?$", t = TIME)).expect("");
+    static ref CLOCK_WORD: Regex = Regex::new(r"\b([Cc]lock) ?([Ii][Nn]|[Oo][Uu][Tt])\b").expect("");
+    static ref EXPENSE: Regex = Regex::new(r"^Expense: *\$([\d.]*),").expect("");
+}
+
+static TIME: &str = r"\d{1,2}:\d{2}(?: ?[AaPp]\.?[Mm]\.?)?";
+
+// "9:05 AM", or "09:05" for a worklog that only takes 24-hour times
+pub fn format_time(time: Time, parse: &ParseOptions) -> String {
+    if parse.time_formats.contains(&TimeFormat::TwelveHour) {
+        time.to_string()
+    } else {
+        format!("{:02}:{:02}", time.hour, time.minute)
+    }
+}
+
+// "9:00 AM clock in (Nervos)", keeping the time and the capital where
+// they were
+fn format_clock(caps: &Captures, parse: &ParseOptions) -> String {
+    let time = |i: usize| caps.get(i).map(|m| {
+        let mut diags = Diagnostics::new();
+        let raw = line_to_raw_entry(&format!("- {}", m.as_str()), parse, &mut diags.line("", 0, ""));
+        match raw {
+            RawEntry::Entry(EntryKind::Time(t)) => format_time(t, parse),
+            _ => m.as_str().to_string(),
+        }
+    });
+    let word = match (caps.get(2), caps.get(3), caps.get(4)) {
+        (Some(clock), Some(dir), _) => format!("{} {}", clock.as_str(), dir.as_str().to_ascii_lowercase()),
+        (_, _, Some(pause)) => pause.as_str().to_string(),
+        _ => unreachable!(),
+    };
+    let mut parts = vec![];
+    parts.extend(time(1));
+    parts.push(word);
+    parts.extend(time(5));
+    if let Some(company) = caps.get(6) {
+        let name = company.as_str().trim_matches(|c| c == '(' || c == ')').trim();
+        parts.push(format!("({})", name));
+    }
+    parts.join(" ")
+}
+
+// "# 2021-02-03", with the weekday if asked for or already there, and
+// anything else after the date kept
+fn format_heading(line: &str, date: NaiveDate, opts: &FormatOptions) -> String {
+    let rest = line[2..].trim_start();
+    let rest = rest.get(10..).unwrap_or("").trim();
+    let had_weekday = is_weekday(rest);
+    let mut heading = format!("# {}", date.format("%Y-%m-%d"));
+    if opts.weekdays || had_weekday {
+        heading.push(' ');
+        heading.push_str(weekday_name(date.weekday()));
+    }
+    if !rest.is_empty() && !had_weekday {
+        heading.push(' ');
+        heading.push_str(rest);
+    }
+    heading
+}
+
+// "Wed", "wednesday" or "(Wed.)"
+fn is_weekday(s: &str) -> bool {
+    let s = s.trim_matches(|c: char| c == '(' || c == ')' || c == ',' || c == '.' || c == '-').trim();
+    s.len() >= 3 && [Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu,
+                     Weekday::Fri, Weekday::Sat, Weekday::Sun].iter().any(|&d| {
+        let name = weekday_name(d);
+        name.len() >= s.len() && name[..s.len()].eq_ignore_ascii_case(s)
+    })
+}
+
+fn weekday_name(day: Weekday) -> &'static str {
+    match day {
+        Weekday::Mon => "Monday",
+        Weekday::Tue => "Tuesday",
+        Weekday::Wed => "Wednesday",
+        Weekday::Thu => "Thursday",
+        Weekday::Fri => "Friday",
+        Weekday::Sat => "Saturday",
+        Weekday::Sun => "Sunday",
+    }
+}
+
+// Whether a cost is written to the cent or less, so showing two
+// decimals loses nothing
+fn cents(cost: &str) -> bool {
+    cost.find('.').is_none_or(|i| cost.len() - i - 1 <= 2)
+}
@@ -0,0 +1,226 @@
+// The record of issued invoices.
+//
+// Each invoice issued is appended to a TOML file, by default
+// `worklog-scan-ledger.toml` next to the worklog. The ledger is used to
+// allocate the next invoice number and to refuse billing the same
+// project for the same days twice.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use chrono::*;
+use toml;
+
+use config::Numbering;
+use sources;
+use chrono_tz::Tz;
+use time_reporting::{BilledDay, BillingPolicy, ExpenseScope, Midnight, Rounding, TagDisplay};
+use errors::*;
+
+pub static LEDGER_FILE_NAME: &str = "worklog-scan-ledger.toml";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Ledger {
+    #[serde(default, rename = "invoice")]
+    pub invoices: Vec<LedgerEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    pub number: String,
+    // The sequence number used to build `number`, if it was allocated
+    pub seq: Option<u32>,
+    // The company named in clock-in lines
+    pub project: Option<String>,
+    // Who was billed
+    pub client: Option<String>,
+    #[serde(with = "date_format")]
+    pub start: NaiveDate,
+    #[serde(with = "date_format")]
+    pub end: NaiveDate,
+    pub hours: f64,
+    pub expenses: f64,
+    pub total: f64,
+    #[serde(default)]
+    pub rate: Option<f64>,
+    pub currency: String,
+    #[serde(default, with = "opt_date_format")]
+    pub issued: Option<NaiveDate>,
+    #[serde(default, with = "opt_date_format")]
+    pub due: Option<NaiveDate>,
+    // How time was rounded; invoices from before rounding was
+    // configurable used the default
+    #[serde(default)]
+    pub rounding: Option<Rounding>,
+    #[serde(default)]
+    pub expense_scope: ExpenseScope,
+    #[serde(default)]
+    pub midnight: Midnight,
+    #[serde(default)]
+    pub timezone: Option<Tz>,
+    #[serde(default)]
+    pub tags: TagDisplay,
+    // The tag billed, as in `--tag review`, if only one was
+    #[serde(default)]
+    pub tag: Option<String>,
+    // Hash of the worklog lines that were billed
+    pub hash: String,
+    // What was billed day by day, for `verify`
+    #[serde(default, rename = "day")]
+    pub days: Vec<BilledDay>,
+}
+
+impl Ledger {
+    // A missing ledger is an empty one
+    pub fn load(path: &Path) -> Result<Ledger> {
+        if !path.exists() {
+            return Ok(Ledger::default());
+        }
+
+        let mut text = String::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut text))
+            .chain_err(|| format!("reading {}", path.display()))?;
+        let ledger = toml::from_str(&text)
+            .chain_err(|| format!("parsing {}", path.display()))?;
+        Ok(ledger)
+    }
+
+    // Written to a temporary file first so a failure can't leave a
+    // truncated ledger behind
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let text = toml::to_string(self).chain_err(|| "serializing ledger")?;
+        sources::write_atomic(path, &text)
+    }
+
+    // Invoices for the same project covering any of the same days
+    pub fn overlapping(&self, project: &Option<String>, start: NaiveDate, end: NaiveDate) -> Vec<&LedgerEntry> {
+        self.invoices.iter().filter(|i| {
+            i.project == *project && i.start <= end && start <= i.end
+        }).collect()
+    }
+
+    pub fn find(&self, number: &str) -> Option<&LedgerEntry> {
+        self.invoices.iter().find(|i| i.number == number)
+    }
+
+    // The next sequence number and the invoice number built from it
+    pub fn next_number(&self, numbering: &Numbering, client: &str, issued: NaiveDate) -> (u32, String) {
+        let last = self.invoices.iter().filter(|i| {
+            let same_client = !numbering.per_client || i.project.as_deref() == Some(client);
+            let same_year = !numbering.yearly || i.issued.map(|d| d.year()) == Some(issued.year());
+            same_client && same_year
+        }).filter_map(|i| i.seq).max();
+        // Past any number given by hand that the sequence would reach
+        let mut seq = last.unwrap_or(0) + 1;
+        let mut number = format_number(&numbering.pattern, client, issued, seq);
+        while self.find(&number).is_some() {
+            seq += 1;
+            number = format_number(&numbering.pattern, client, issued, seq);
+        }
+        (seq, number)
+    }
+
+    pub fn record(&mut self, entry: LedgerEntry) -> Result<()> {
+        if self.find(&entry.number).is_some() {
+            bail!("invoice {} is already in the ledger", entry.number);
+        }
+        self.invoices.push(entry);
+        Ok(())
+    }
+}
+
+impl LedgerEntry {
+    // The policy the invoice was billed under
+    pub fn policy(&self) -> BillingPolicy {
+        BillingPolicy {
+            rounding: self.rounding.clone().unwrap_or_default(),
+            expense_scope: self.expense_scope,
+            midnight: self.midnight,
+            timezone: self.timezone,
+            tags: self.tags,
+            tag: self.tag.as_ref().and_then(|tag| tag.parse().ok()),
+        }
+    }
+}
+
+pub fn default_path(worklog: &Path) -> PathBuf {
+    sources::directory(worklog).join(LEDGER_FILE_NAME)
+}
+
+// Expand `{client}`, `{year}`, `{month}` and `{seq}` in a numbering
+// pattern. `{seq:03}` pads the sequence number with zeros to 3 digits.
+pub fn format_number(pattern: &str, client: &str, issued: NaiveDate, seq: u32) -> String {
+    let mut out = String::new();
+    let mut rest = pattern;
+    while let Some(open) = rest.find('{') {
+        out.push_str(&rest[..open]);
+        let close = match rest[open..].find('}') {
+            Some(close) => open + close,
+            None => break,
+        };
+        let field = &rest[open + 1 .. close];
+        let (name, width) = match field.find(':') {
+            Some(i) => (&field[..i], field[i + 1..].parse().unwrap_or(0)),
+            None => (field, 0),
+        };
+        match name {
+            "client" => out.push_str(client),
+            "year" => out.push_str(&issued.year().to_string()),
+            "month" => out.push_str(&format!("{:02}", issued.month())),
+            "seq" => out.push_str(&format!("{:0width$}", seq, width = width)),
+            _ => out.push_str(&rest[open ..= close]),
+        }
+        rest = &rest[close + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+// FNV-1a, which unlike the std hashers is stable across Rust versions
+pub fn hash_lines<'a, I: IntoIterator<Item = &'a str>>(lines: I) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for line in lines {
+        for byte in line.bytes().chain(Some(b'\n')) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    format!("fnv1a64:{:016x}", hash)
+}
+
+pub mod date_format {
+    use chrono::NaiveDate;
+    use serde::{Deserialize, Deserializer, Serializer};
+    use serde::de::Error;
+
+    pub fn serialize<S: Serializer>(date: &NaiveDate, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&date.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<NaiveDate, D::Error> {
+        let s = String::deserialize(d)?;
+        NaiveDate::parse_from_str(&s, "%Y-%m-%d").map_err(D::Error::custom)
+    }
+}
+
+pub mod opt_date_format {
+    use chrono::NaiveDate;
+    use serde::{Deserialize, Deserializer, Serializer};
+    use serde::de::Error;
+
+    pub fn serialize<S: Serializer>(date: &Option<NaiveDate>, s: S) -> Result<S::Ok, S::Error> {
+        match *date {
+            Some(ref date) => s.serialize_str(&date.to_string()),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<NaiveDate>, D::Error> {
+        let s = Option::<String>::deserialize(d)?;
+        match s {
+            Some(s) => NaiveDate::parse_from_str(&s, "%Y-%m-%d").map(Some).map_err(D::Error::custom),
+            None => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,64 @@
+// Reading and reporting on markdown worklogs.
+//
+// A worklog is a series of `# YYYY-MM-DD` headings, each followed by
+// `- ` bullets: actions, timestamps, clock-ins and clock-outs,
+// pleasure/pain predictions and expenses. `parse` turns one into a
+// `Worklog`, which the reports in `time_reporting` and
+// `pleasure_and_pain` consume; `parse_sources` does the same for one
+// split across several files.
+
+#[macro_use]
+extern crate error_chain;
+extern crate regex;
+extern crate chrono;
+extern crate chrono_tz;
+#[macro_use]
+extern crate lazy_static;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate toml;
+extern crate minijinja;
+extern crate serde_json;
+extern crate csv;
+
+pub mod append;
+pub mod config;
+pub mod diagnostics;
+pub mod formatter;
+pub mod ledger;
+pub mod model;
+pub mod parser;
+pub mod lint;
+pub mod pleasure_and_pain;
+pub mod render;
+pub mod sources;
+pub mod status;
+pub mod time_reporting;
+pub mod verify;
+
+pub use diagnostics::{Diagnostic, Diagnostics, Severity};
+pub use model::*;
+pub use parser::{parse_sources, parse_worklog, parse_worklog_with, ParseOptions, RawEntry, TimeFormat};
+pub use sources::Sources;
+
+#[allow(deprecated)]
+pub mod errors {
+    error_chain! {
+        foreign_links {
+            Io(::std::io::Error);
+        }
+    }
+}
+
+// Parse a worklog, failing if there are any errors. Use `parse_worklog`
+// to get the worklog even when some lines are bad, along with the
+// warnings.
+pub fn parse(text: &str) -> ::std::result::Result<Worklog, Diagnostics> {
+    let (worklog, diags) = parse_worklog("<input>", text);
+    if diags.has_errors() {
+        Err(diags)
+    } else {
+        Ok(worklog)
+    }
+}
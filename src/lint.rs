@@ -0,0 +1,174 @@
+// Structural checks over a whole worklog.
+//
+// `do_time_report` only looks at the days it is reporting on. This
+// walks every day and records every problem it can find, so it can be
+// run as a pre-commit hook. Unmatched clock-ins and clock-outs, and
+// pauses without a resume, are already reported while parsing, since
+// sessions can't be built without them. Session lengths are checked
+// for every command, as a forgotten clock-out can pair with one days
+// later.
+
+use std::collections::{BTreeMap, HashMap};
+use chrono::*;
+
+use diagnostics::Diagnostics;
+use model::*;
+use parser::{body_span, describe};
+use sources::Sources;
+
+pub fn lint(sources: &Sources, worklog: &Worklog, known_companies: &[String], diags: &mut Diagnostics) {
+    let lines = sources.lines();
+
+    check_headings(sources, &lines, worklog, diags);
+    check_companies(sources, &lines, worklog, known_companies, diags);
+
+    for day in &worklog.days {
+        check_timestamps(sources, &lines, day, diags);
+        check_durations(sources, &lines, worklog, day, diags);
+    }
+}
+
+pub static DEFAULT_MAX_SESSION_HOURS: f64 = 16.0;
+
+// Sessions longer than `max_hours` probably lost their clock-out
+pub fn check_session_lengths(sources: &Sources, worklog: &Worklog, max_hours: f64, diags: &mut Diagnostics) {
+    let lines = sources.lines();
+    for session in worklog.sessions() {
+        let minutes = session.elapsed_minutes();
+        if minutes as f64 > max_hours * 60.0 {
+            let source = lines[session.line - 1];
+            sources.diag(diags, session.line)
+                .error(body_span(source),
+                       format!("session is open for {}:{:02} hours until {}, more than the \
+                                maximum of {} hours; is a clock-out missing?",
+                               minutes / 60, minutes % 60, sources.line_ref(session.end_line, session.line),
+                               max_hours));
+        }
+    }
+}
+
+// One heading per date. Headings out of order, and the same date in
+// two files that both clock sessions, are reported while parsing.
+fn check_headings(sources: &Sources, lines: &[&str], worklog: &Worklog, diags: &mut Diagnostics) {
+    let mut seen: HashMap<NaiveDate, usize> = HashMap::new();
+
+    for day in &worklog.days {
+        let file = &sources.locate(day.line).0.path;
+        match seen.get(&day.date) {
+            Some(&first) if sources.locate(first).0.path == *file => {
+                let source = lines[day.line - 1];
+                sources.diag(diags, day.line)
+                    .error(body_span(source), format!("duplicate heading for {}, first seen on {}",
+                                                      day.date, sources.line_ref(first, day.line)));
+            }
+            Some(_) => { }
+            None => {
+                seen.insert(day.date, day.line);
+            }
+        }
+    }
+}
+
+fn entry_company(entry: &Entry) -> Option<&str> {
+    match entry.kind {
+        EntryKind::ClockIn(Clock { company: Some(ref c), .. }) |
+        EntryKind::ClockOut(Clock { company: Some(ref c), .. }) |
+        EntryKind::Pause(Clock { company: Some(ref c), .. }) |
+        EntryKind::Resume(Clock { company: Some(ref c), .. }) |
+        EntryKind::Break(LoggedTime { company: Some(ref c), .. }) |
+        EntryKind::Duration(LoggedTime { company: Some(ref c), .. }) => Some(c),
+        _ => None,
+    }
+}
+
+// Company names that aren't in the known list are errors. Without a
+// list, names that differ only in case or spacing from a more commonly
+// used spelling are probably typos.
+fn check_companies(sources: &Sources, lines: &[&str], worklog: &Worklog,
+                   known_companies: &[String], diags: &mut Diagnostics) {
+    let entries = || worklog.days.iter().flat_map(|d| d.entries.iter());
+
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for company in entries().filter_map(entry_company) {
+        *counts.entry(company).or_insert(0) += 1;
+    }
+
+    let normalize = |s: &str| s.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase();
+
+    for entry in entries() {
+        let company = match entry_company(entry) {
+            Some(c) => c,
+            None => continue,
+        };
+
+        let source = lines[entry.line - 1];
+        let mut diag = sources.diag(diags, entry.line);
+        let span = company_span(source, company);
+
+        if !known_companies.is_empty() {
+            if !known_companies.iter().any(|k| k == company) {
+                diag.error(span, format!("unknown company '{}'", company));
+            }
+            continue;
+        }
+
+        let more_common = counts.iter().find(|&(other, &n)| {
+            *other != company && normalize(other) == normalize(company) && n > counts[company]
+        });
+        if let Some((other, _)) = more_common {
+            diag.warning(span, format!("company '{}' is elsewhere spelled '{}'", company, other));
+        }
+    }
+}
+
+fn company_span(line: &str, company: &str) -> ::std::ops::Range<usize> {
+    match line.find(&format!("({})", company)) {
+        Some(open) => open + 1 .. open + 1 + company.len(),
+        None => body_span(line),
+    }
+}
+
+// Time logged as a duration on a day the same company was clocked may
+// be billed twice
+fn check_durations(sources: &Sources, lines: &[&str], worklog: &Worklog, day: &Day, diags: &mut Diagnostics) {
+    for (line, duration) in day.durations() {
+        let clocked = worklog.sessions().find(|s| {
+            s.company == duration.company && s.date <= day.date && day.date <= s.end_date
+        });
+        if let Some(session) = clocked {
+            let source = lines[line - 1];
+            sources.diag(diags, line)
+                .warning(body_span(source),
+                         format!("{} minutes logged {}on a day clocked in on {}; \
+                                  the time may be billed twice", duration.minutes,
+                                 describe(&duration.company), sources.line_ref(session.line, line)));
+        }
+    }
+}
+
+// Entries within a day are written in the order they happen
+fn check_timestamps(sources: &Sources, lines: &[&str], day: &Day, diags: &mut Diagnostics) {
+    let mut prev: Option<Time> = None;
+
+    for entry in &day.entries {
+        let time = match entry.kind {
+            EntryKind::Time(time) |
+            EntryKind::ClockIn(Clock { time: Some(time), .. }) |
+            EntryKind::ClockOut(Clock { time: Some(time), .. }) |
+            EntryKind::Pause(Clock { time: Some(time), .. }) |
+            EntryKind::Resume(Clock { time: Some(time), .. }) => Some(time),
+            _ => None,
+        };
+        if let Some(time) = time {
+            if let Some(prev) = prev {
+                if time < prev {
+                    let source = lines[entry.line - 1];
+                    sources.diag(diags, entry.line)
+                        .error(body_span(source),
+                               format!("timestamp goes backwards from {} to {}", prev, time));
+                }
+            }
+            prev = Some(time);
+        }
+    }
+}
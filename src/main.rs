@@ -1,247 +1,510 @@
 #[macro_use]
 extern crate error_chain;
-extern crate regex;
 extern crate chrono;
-#[macro_use]
-extern crate lazy_static;
+extern crate chrono_tz;
+extern crate clap;
+extern crate worklog_scan;
 
+use std::collections::{BTreeMap, BTreeSet};
 use std::env;
-use std::fs::File;
-use std::io::{BufReader, BufRead};
-use std::str::FromStr;
-use errors::*;
-use regex::Regex;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
 use chrono::*;
+use clap::Parser;
+
+mod cli;
+use cli::*;
+
+use worklog_scan::append::{self, TimeSource};
+use worklog_scan::config::Config;
+use worklog_scan::{DayOrder, Diagnostics, EntryKind, Sources, Worklog};
+use worklog_scan::errors::*;
+use worklog_scan::formatter;
+use worklog_scan::ledger::{self, Ledger, LedgerEntry};
+use worklog_scan::lint;
+use worklog_scan::parse_sources;
+use worklog_scan::pleasure_and_pain as pp;
+use worklog_scan::render;
+use worklog_scan::sources;
+use worklog_scan::status;
+use worklog_scan::time_reporting as tr;
+use worklog_scan::verify;
 
-mod pleasure_and_pain;
-use pleasure_and_pain as pp;
-
-mod time_reporting;
-use time_reporting as tr;
-
-#[allow(deprecated)]
-mod errors {
-    error_chain! {
-        foreign_links {
-            Io(::std::io::Error);
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("error: {}", e);
+        for e in e.iter().skip(1) {
+            eprintln!("caused by: {}", e);
         }
+        process::exit(1);
     }
 }
 
-fn main() {
-    run().unwrap();
+fn run() -> Result<()> {
+    let args = Args::parse();
+
+    let config = match args.config {
+        Some(ref path) => Config::load(Path::new(path))?,
+        None => {
+            let found = Config::find(args.file.first().map(Path::new))?;
+            found.map(|(_, config)| config).unwrap_or_default()
+        }
+    };
+
+    let files = match (args.file.is_empty(), config.worklog.clone()) {
+        (false, _) => args.file,
+        (true, Some(file)) => vec![file],
+        (true, None) => bail!("no worklog file given, use --file or set `worklog` in the config file"),
+    };
+
+    process_files(&files, &config, args.command)
 }
 
-#[derive(Debug)]
-enum Mode {
-    PleasureAndPain,
-    TimeReporting(NaiveDate, NaiveDate, f64, String, Option<String>, Option<String>, Option<u32>, Option<NaiveDate>, Option<NaiveDate>),
+// What to add to the worklog, for the commands that do
+fn new_entry(command: &Command) -> Option<append::NewEntry> {
+    match *command {
+        Command::Clock(ref args) if args.direction == ClockDirection::In => {
+            Some(append::NewEntry::ClockIn(args.company.clone()))
+        }
+        Command::Clock(ref args) => Some(append::NewEntry::ClockOut(args.company.clone())),
+        Command::Note(ref args) => Some(append::NewEntry::Note(args.text.clone())),
+        Command::Expense(ref args) => Some(append::NewEntry::Expense(args.cost, args.what.clone())),
+        _ => None,
+    }
 }
 
-fn run() -> Result<()> {
-    let file = env::args().skip(1).next();
-
-    if let Some(ref file) = file {
-        let mode = env::args().skip(2).next();
-        let mode = if let Some(ref mode) = mode {
-            if mode == "pp" {
-                Mode::PleasureAndPain
-            } else if mode == "tr" {
-                let start = env::args().skip(3).next();
-                let end = env::args().skip(4).next();
-                match (start, end) {
-                    (Some(ref start), Some(ref end)) => {
-                        let start: ParseResult<NaiveDate> = NaiveDate::parse_from_str(start, "%Y-%m-%d");
-                        let end: ParseResult<NaiveDate> = NaiveDate::parse_from_str(end, "%Y-%m-%d");
-                        let start = start.map_err(|e| e.to_string())?;
-                        let end = end.map_err(|e| e.to_string())?;
-                        let rate = f64::from_str(&env::args().skip(5).next().expect("rate")).expect("rate");
-                        let self_name = env::args().skip(6).next().expect("self-name");
-                        let project = env::args().skip(7).next();
-                        let client = env::args().skip(8).next();
-                        let client = client.clone().or_else(|| project.clone());
-                        let invoice_no = env::args().skip(9).next().map(|s| s.parse().expect("invoice-no"));
-                        let issue_date = env::args().skip(10).next().map(|s| NaiveDate::parse_from_str(&s, "%Y-%m-%d").expect("issue-date"));
-                        let due_date = env::args().skip(11).next().map(|s| NaiveDate::parse_from_str(&s, "%Y-%m-%d").expect("issue-date"));
-                        Mode::TimeReporting(start, end, rate, self_name, project, client, invoice_no, issue_date, due_date)
-                    }
-                    _ => {
-                        bail!("no start or end for time report");
-                    }
+// Each file separately, so that in place they are rewritten one by one
+fn format_files(sources: &Sources, config: &Config, args: &FmtArgs) -> Result<()> {
+    let opts = formatter::FormatOptions { weekdays: args.weekdays };
+    let mut changed = 0;
+    for file in sources.files() {
+        let formatted = formatter::format_worklog(&file.text, &config.parse_options(), &opts);
+        if args.check {
+            let new: Vec<&str> = formatted.lines().collect();
+            for (i, old) in file.text.lines().enumerate() {
+                if new[i] != old {
+                    println!("{}:{}", file.path, i + 1);
+                    println!("  - {}", old);
+                    println!("  + {}", new[i]);
                 }
-            } else {
-                bail!("unknown mode");
+            }
+        } else if args.in_place {
+            if formatted != file.text {
+                let path = Path::new(&file.path);
+                let backup = format!("{}.bak", file.path);
+                fs::copy(path, &backup).chain_err(|| format!("writing {}", backup))?;
+                sources::write_atomic(path, &formatted)?;
+                eprintln!("reformatted {}", file.path);
             }
         } else {
-            bail!("no mode");
-        };
-        process_file(file, mode)    } else {
-        bail!("no file");
-    }
-}
-
-fn process_file(file: &str, mode: Mode) -> Result<()> {
-    let lines = BufReader::new(File::open(file)?)
-        .lines()
-        .filter_map(|l| l.ok());
-    let lines = lines.collect::<Vec<_>>();
-
-    let raw_entries = lines.iter().map(|ref s| line_to_raw_entry(s));
-    let raw_entries: Vec<_> = raw_entries.collect();
-
-    match mode {
-        Mode::PleasureAndPain => {
-            let entries = pp::raw_to_entries(&raw_entries);
-            pp::analyze_prediction(&entries)?;
+            print!("{}", formatted);
         }
-        Mode::TimeReporting(start, end, rate, self_name, project, client, invoice_no, issue_date, due_date) => {
-            tr::do_time_report(&raw_entries, start, end, rate, self_name, project, client, invoice_no, issue_date, due_date)?;
+        if formatted != file.text {
+            changed += 1;
         }
     }
-
+    if args.check && changed > 0 {
+        bail!("{} of {} files would be reformatted", changed, sources.files().len());
+    }
     Ok(())
 }
 
-// Determine what each individual line represents
-fn line_to_raw_entry(line: &str) -> RawEntry {
-    let line_lcase = line.to_lowercase();
-
-    if line_lcase.contains("clockin") {
-        panic!("use 'clock in', not 'clockin'");
+fn process_files(files: &[String], config: &Config, command: Command) -> Result<()> {
+    let sources = Sources::load(files)?;
+    if let Command::Fmt(ref fmt_args) = command {
+        return format_files(&sources, config, fmt_args);
     }
-
-    if line_lcase.contains("clockout") {
-        panic!("use 'clock out', not 'clockout'");
+    if let Some(entry) = new_entry(&command) {
+        let addition = append::add_entry(&sources, &config.parse_options(), &entry, &append::SystemTime)?;
+        sources::write_atomic(Path::new(&addition.path), &addition.text)?;
+        for line in &addition.lines {
+            println!("{}", line);
+        }
+        return Ok(());
     }
-
-    if line_lcase.contains("clock in") {
-        let company = parse_company(line);
-        return RawEntry::ClockIn(company);
+    // The ledger and relative paths go by the first file
+    let file = &sources.files()[0].path;
+    let lines = sources.lines();
+
+    let (worklog, mut diags) = parse_sources(&sources, &config.parse_options());
+    // Quietly, as it may run with every shell prompt
+    if let Command::Status(ref status_args) = command {
+        let status = status::status(&worklog, append::SystemTime.now());
+        if status_args.short {
+            println!("{}", status.one_line());
+        } else {
+            println!("{}", status);
+        }
+        return Ok(());
     }
+    let max_session_hours = config.max_session_hours.unwrap_or(lint::DEFAULT_MAX_SESSION_HOURS);
+    lint::check_session_lengths(&sources, &worklog, max_session_hours, &mut diags);
 
-    if line_lcase.contains("clock out") {
-        let company = parse_company(line);
-        return RawEntry::ClockOut(company);
+    if let Command::Lint(ref lint_args) = command {
+        let known_companies = if lint_args.companies.is_empty() {
+            config.clients.keys().cloned().collect()
+        } else {
+            lint_args.companies.clone()
+        };
+        lint::lint(&sources, &worklog, &known_companies, &mut diags);
+        diags.emit();
+        if diags.has_errors() {
+            bail!("{} has errors", files.join(", "));
+        }
+        return Ok(());
     }
 
-    if line.starts_with("# ") {
-        let line = &line[2..];
-        if let Some(date) = parse_date(line) {
-            return RawEntry::NewDay(date);
+    // Bad lines have been turned into junk, so keep going and show
+    // everything that was wrong at once.
+    diags.emit();
+
+    match command {
+        Command::Lint(..) | Command::Fmt(..) |
+        Command::Clock(..) | Command::Note(..) | Command::Expense(..) |
+        Command::Status(..) => unreachable!(),
+        Command::Verify(ref verify_args) => {
+            let mut ledger = Ledger::load(&ledger_path(config, file))?;
+            if !verify_args.invoices.is_empty() {
+                for number in &verify_args.invoices {
+                    if ledger.find(number).is_none() {
+                        bail!("invoice {} is not in the ledger", number);
+                    }
+                }
+                ledger.invoices.retain(|i| verify_args.invoices.contains(&i.number));
+            }
+
+            let changes = verify::verify(&worklog, &lines, &ledger);
+            for change in &changes {
+                println!("{}", change);
+            }
+            if !changes.is_empty() {
+                bail!("{} of {} invoices no longer match the worklog", changes.len(), ledger.invoices.len());
+            }
+            eprintln!("{} invoices match the worklog", ledger.invoices.len());
         }
-    }
+        Command::Pp(ref pp_args) => {
+            let entries = pp::worklog_to_entries(&worklog);
+            pp::analyze_prediction(&entries, pp_args.order)?;
+        }
+        Command::Invoice(ref invoice) => {
+            let mut opts = invoice_options(invoice, config)?;
+            check_period(&worklog, &sources, &diags, opts.start, opts.end)?;
+
+            let ledger_path = ledger_path(config, file);
+            let mut ledger = Ledger::load(&ledger_path)?;
+            check_overlaps(&ledger, &opts, invoice.dry_run)?;
+            // Before writing anything, as recording would fail
+            if let Some(ref number) = opts.invoice_no {
+                if !invoice.dry_run && ledger.find(number).is_some() {
+                    bail!("invoice {} is already in the ledger", number);
+                }
+            }
+            let seq = allocate_number(&ledger, config, &mut opts);
 
-    if !line.starts_with("- ") {
-        return RawEntry::Junk(line.to_string());
-    }
+            let billed = match invoice.output {
+                Some(ref path) => {
+                    let mut out = File::create(path).chain_err(|| format!("creating {}", path))?;
+                    tr::do_time_report(&worklog, &opts, &mut out)?
+                }
+                None => {
+                    let stdout = io::stdout();
+                    tr::do_time_report(&worklog, &opts, &mut stdout.lock())?
+                }
+            };
 
-    let line = &line[2..];
-    let line = line.trim();
+            if let Some(ref path) = invoice.expenses_output {
+                let report = tr::InvoiceReport::new(&opts, &billed);
+                let mut out = File::create(path).chain_err(|| format!("creating {}", path))?;
+                render::write_expenses_csv(&report, opts.csv_delimiter, &mut out)?;
+            }
 
-    if let Some(time) = parse_time(line) {
-        return RawEntry::Time(time.0, time.1);
-    }
+            if !invoice.dry_run {
+                let number = opts.invoice_no.clone().expect("");
+                ledger.record(ledger_entry(&opts, seq, billed, &lines))?;
+                ledger.save(&ledger_path)?;
+                eprintln!("recorded invoice {} in {}", number, ledger_path.display());
+            }
+        }
+        Command::Batch(ref batch) => {
+            let (start, end) = period(&batch.period)?;
+            check_period(&worklog, &sources, &diags, start, end)?;
+
+            let companies: BTreeSet<_> = worklog.days_between(start, end)
+                .flat_map(|d| {
+                    d.sessions.iter().map(|s| s.company.clone())
+                        .chain(d.durations().map(|(_, l)| l.company.clone()))
+                })
+                .collect();
+            if companies.contains(&None) {
+                eprintln!("warning: sessions without a company are not invoiced");
+            }
 
-    if let Some(ppp) = parse_prediction(line) {
-        return RawEntry::Prediction(ppp.0, ppp.1, ppp.2, ppp.3);
-    }
+            // Everything is checked before any invoice is written
+            let mut all_opts = vec![];
+            for company in companies.into_iter().flatten() {
+                let args = InvoiceArgs {
+                    period: batch.period.clone(),
+                    name: batch.name.clone(),
+                    client: Some(company),
+                    issued: batch.issued,
+                    due: batch.due,
+                    format: batch.format.clone(),
+                    templates: batch.templates.clone(),
+                    delimiter: batch.delimiter,
+                    dry_run: batch.dry_run,
+                    ..InvoiceArgs::default()
+                };
+                let mut opts = invoice_options(&args, config)
+                    .chain_err(|| format!("invoicing {}", args.client.as_ref().expect("")))?;
+                opts.policy.expense_scope = tr::ExpenseScope::Sessions;
+                all_opts.push(opts);
+            }
 
-    if let Some((cost, what)) = parse_expense(line) {
-        return RawEntry::Expense(cost, what);
-    }
+            for day in worklog.days_between(start, end) {
+                for entry in &day.entries {
+                    if let EntryKind::Expense(ref expense) = entry.kind {
+                        let in_session = worklog.sessions_at(entry.line).any(|s| s.company.is_some());
+                        if !in_session {
+                            eprintln!("warning: {}: expense '{}' is not within a session, so is not invoiced",
+                                      sources.describe(entry.line), expense.what.trim());
+                        }
+                    }
+                }
+            }
 
-    return RawEntry::Action(line.to_string());
-}
+            let ledger_path = ledger_path(config, file);
+            let mut ledger = Ledger::load(&ledger_path)?;
+            for opts in &all_opts {
+                check_overlaps(&ledger, opts, batch.dry_run)?;
+            }
+
+            let out_dir = Path::new(&batch.out_dir);
+            let mut summary = vec![];
+            for mut opts in all_opts {
+                // Recorded even for a dry run, so the numbers are allocated
+                // as they would be
+                let seq = allocate_number(&ledger, config, &mut opts);
+                let number = opts.invoice_no.clone().expect("");
+                let path = out_dir.join(format!("{}.{}", file_name(&number), extension(&opts.format)));
+                let mut out = File::create(&path).chain_err(|| format!("creating {}", path.display()))?;
+                let billed = tr::do_time_report(&worklog, &opts, &mut out)?;
+                summary.push((opts.client.clone().unwrap_or_default(), number, billed.hours(),
+                              billed.amount_due(opts.rate), opts.currency.clone(), path));
+                ledger.record(ledger_entry(&opts, seq, billed, &lines))?;
+            }
 
-fn parse_company(line: &str) -> Option<String> {
-    let open_paren_idx = line.rfind("(");
-    let close_paren_idx = line.rfind(")");
-    match (open_paren_idx, close_paren_idx) {
-        (Some(open_paren_idx), Some(close_paren_idx)) => {
-            let region = &line[open_paren_idx + 1 .. close_paren_idx];
-            let region = region.trim();
-            Some(region.to_string())
+            println!("{:<24} {:<16} {:>8} {:>12}  file", "client", "invoice", "hours", "amount");
+            for &(ref client, ref number, hours, amount, ref currency, ref path) in &summary {
+                println!("{:<24} {:<16} {:>8.2} {:>12.2} {}  {}",
+                         client, number, hours, amount, currency, path.display());
+            }
+            let mut totals: BTreeMap<&str, (f64, f64)> = BTreeMap::new();
+            for &(_, _, hours, amount, ref currency, _) in &summary {
+                let total = totals.entry(currency).or_insert((0.0, 0.0));
+                total.0 += hours;
+                total.1 += amount;
+            }
+            for (currency, (hours, amount)) in totals {
+                println!("{:<24} {:<16} {:>8.2} {:>12.2} {}", "total", "", hours, amount, currency);
+            }
+
+            if !batch.dry_run && !summary.is_empty() {
+                ledger.save(&ledger_path)?;
+                eprintln!("recorded {} invoices in {}", summary.len(), ledger_path.display());
+            }
         }
-        _ => None,
     }
-}
 
-#[derive(Debug)]
-pub enum RawEntry {
-    Junk(String),
-    NewDay(String),
-    Action(String), // text, url
-    Time(u8, u8), // hour (0-23), minute
-    // predictud pleasure, pain, actual pleasure, pain
-    Prediction(u8, u8, u8, u8),
-    ClockIn(Option<String>),
-    ClockOut(Option<String>),
-    Expense(f64, String),
+    Ok(())
 }
 
-fn parse_date(s: &str) -> Option<String> {
-    lazy_static! {
-        static ref REGEX: Regex = Regex::new(r"^(\d{4})-(\d{2})-(\d{2})").expect("");
-    }
-    if REGEX.is_match(s) {
-        Some(s.to_string())
-    } else {
-        None
-    }
+fn ledger_path(config: &Config, worklog: &str) -> PathBuf {
+    config.ledger.as_ref().map(PathBuf::from)
+        .unwrap_or_else(|| ledger::default_path(Path::new(worklog)))
 }
 
-fn parse_time(s: &str) -> Option<(u8, u8)> {
-    lazy_static! {
-        static ref REGEX: Regex = Regex::new(r"^(\d{1,2}):(\d{2}) (AM|PM)").expect("");
+// Problems elsewhere in the worklog don't affect a report
+fn check_period(worklog: &Worklog, sources: &Sources, diags: &Diagnostics,
+                start: NaiveDate, end: NaiveDate) -> Result<()> {
+    let bad_days: Vec<_> = worklog.days_between(start, end).filter(|day| {
+        diags.errors().any(|d| sources.line_number(&d.path, d.line).is_some_and(|l| day.contains_line(l)))
+    }).map(|day| day.date.to_string()).collect();
+    if !bad_days.is_empty() {
+        bail!("errors in the reporting period on {}", bad_days.join(", "));
     }
-    if let Some(caps) = REGEX.captures(s) {
-        let mut hour: u8 = str::parse(&caps[1]).expect("");
-        let minute: u8 = str::parse(&caps[2]).expect("");
-        let am_pm = &caps[3];
+    Ok(())
+}
 
-        if hour == 12 {
-            hour = 0;
+fn check_overlaps(ledger: &Ledger, opts: &tr::InvoiceOptions, dry_run: bool) -> Result<()> {
+    for other in ledger.overlapping(&opts.project, opts.start, opts.end) {
+        let msg = format!("{} - {} overlaps invoice {} for {} - {}",
+                          opts.start, opts.end, other.number, other.start, other.end);
+        if dry_run {
+            eprintln!("warning: {}", msg);
+        } else {
+            bail!("{}; use --dry-run to print it without recording", msg);
         }
+    }
+    Ok(())
+}
 
-        if am_pm == "PM" {
-            hour += 12;
+// Take the next invoice number from the ledger, unless one was given
+fn allocate_number(ledger: &Ledger, config: &Config, opts: &mut tr::InvoiceOptions) -> Option<u32> {
+    match opts.invoice_no {
+        Some(_) => None,
+        None => {
+            let key = opts.project.clone().unwrap_or_default();
+            let issued = opts.issue_date.unwrap_or_else(|| Local::now().date_naive());
+            let (seq, number) = ledger.next_number(&config.numbering, &key, issued);
+            opts.invoice_no = Some(number);
+            Some(seq)
         }
-
-        Some((hour, minute))
-    } else {
-        None
     }
 }
 
-fn parse_prediction(s: &str) -> Option<(u8, u8, u8, u8)> {
-    lazy_static! {
-        static ref REGEX: Regex = Regex::new(r"^(\d+)/(\d+):(\d+)/(\d+)").expect("");
+fn ledger_entry(opts: &tr::InvoiceOptions, seq: Option<u32>, billed: tr::Billed, lines: &[&str]) -> LedgerEntry {
+    LedgerEntry {
+        number: opts.invoice_no.clone().expect(""),
+        seq,
+        project: opts.project.clone(),
+        client: opts.client.clone(),
+        start: opts.start,
+        end: opts.end,
+        hours: billed.hours(),
+        expenses: billed.expenses(),
+        total: billed.amount_due(opts.rate),
+        rate: Some(opts.rate),
+        currency: opts.currency.clone(),
+        rounding: Some(opts.policy.rounding.clone()),
+        expense_scope: opts.policy.expense_scope,
+        midnight: opts.policy.midnight,
+        timezone: opts.policy.timezone,
+        tags: opts.policy.tags,
+        tag: opts.policy.tag.as_ref().map(|tag| tag.to_string()),
+        issued: opts.issue_date,
+        due: opts.due_date,
+        hash: ledger::hash_lines(billed.lines.iter().map(|&l| lines[l - 1])),
+        days: billed.days,
     }
-    if let Some(caps) = REGEX.captures(s) {
-        let pr_pl: u8 = str::parse(&caps[1]).expect("");
-        let pr_pn: u8 = str::parse(&caps[2]).expect("");
-        let ac_pl: u8 = str::parse(&caps[3]).expect("");
-        let ac_pn: u8 = str::parse(&caps[4]).expect("");
-        Some((pr_pl, pr_pn, ac_pl, ac_pn))
-    } else {
-        None
+}
+
+// An invoice number made safe to use as a file name
+fn file_name(number: &str) -> String {
+    number.chars().map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' }).collect()
+}
+
+fn extension(format: &str) -> &str {
+    match format {
+        "json" => "json",
+        "csv" => "csv",
+        _ => "html",
     }
 }
 
-fn parse_expense(s: &str) -> Option<(f64, String)> {
-    if !s.to_ascii_lowercase().starts_with("expense:") {
-        return None;
+fn period(args: &PeriodArgs) -> Result<(NaiveDate, NaiveDate)> {
+    let (start, end) = match (args.month, args.from, args.to) {
+        (Some(month), _, _) => month,
+        (None, Some(from), Some(to)) => (from, to),
+        _ => bail!("give the reporting period with --month, or --from and --to"),
+    };
+    if end < start {
+        bail!("--to {} is before --from {}", end, start);
     }
+    Ok((start, end))
+}
 
-    lazy_static! {
-        static ref REGEX: Regex = Regex::new(r"^Expense: *\$((?:\d|\.)*),(.*)").expect("");
+// Combine the command line with the client's profile, the command line
+// taking precedence
+fn invoice_options(args: &InvoiceArgs, config: &Config) -> Result<tr::InvoiceOptions> {
+    let (start, end) = period(&args.period)?;
+
+    // Profiles are keyed by the company named in clock-in lines
+    let key = args.client.as_ref().or(args.project.as_ref());
+    let profile = key.and_then(|k| config.client(k));
+    let project = match profile {
+        Some(_) => args.project.clone().or_else(|| key.cloned()),
+        None => args.project.clone(),
+    };
+    let client = match profile {
+        Some(profile) => profile.name.clone().or_else(|| key.cloned()),
+        None => args.client.clone(),
+    };
+    let client = client.or_else(|| project.clone());
+
+    let rate = match args.rate.or_else(|| profile.and_then(|p| p.rate)) {
+        Some(rate) => rate,
+        None => bail!("no rate, use --rate or set `rate` in the client profile"),
+    };
+    if !(rate >= 0.0 && rate.is_finite()) {
+        bail!("rate must be a non-negative number, not {}", rate);
     }
-    if let Some(caps) = REGEX.captures(s) {
-        let cost: f64 = str::parse(&caps[1]).expect("float expense cost");
-        let what: String = caps[2].to_string();
-        return Some((cost, what));
-    } else {
-        panic!("malformed expense: {}", s);
+
+    let self_name = match args.name.clone().or_else(|| config.identity.name.clone()) {
+        Some(name) => name,
+        None => bail!("no name to invoice from, use --name or set `name` under [identity] in the config file"),
+    };
+
+    // With a profile there is enough to issue the invoice today
+    let issue_date = match (args.issued, profile) {
+        (Some(issued), _) => Some(issued),
+        (None, Some(_)) => Some(Local::now().date_naive()),
+        (None, None) => None,
+    };
+    let payment_terms = profile.and_then(|p| p.payment_terms);
+    let due_date = match (args.due, issue_date, payment_terms) {
+        (Some(due), _, _) => Some(due),
+        (None, Some(issued), Some(days)) => Some(issued + Duration::days(days as i64)),
+        _ => None,
+    };
+    if let (Some(issued), Some(due)) = (issue_date, due_date) {
+        if due < issued {
+            bail!("due date {} is before issue date {}", due, issued);
+        }
     }
-}
 
+    let format = args.format.clone()
+        .or_else(|| env::var("OUTPUT").ok())
+        .or_else(|| profile.and_then(|p| p.style.clone()))
+        .unwrap_or_else(|| "normal".to_string());
+    let mut rounding = profile.and_then(|p| p.rounding.clone()).unwrap_or_default();
+    rounding.increment = args.round_increment.unwrap_or(rounding.increment);
+    rounding.mode = args.round_mode.unwrap_or(rounding.mode);
+    rounding.per = args.round_per.unwrap_or(rounding.per);
+    rounding.minimum = args.round_minimum.unwrap_or(rounding.minimum);
+
+    let opts = tr::InvoiceOptions {
+        start,
+        end,
+        rate,
+        currency: profile.and_then(|p| p.currency.clone()).unwrap_or_else(|| "USD".to_string()),
+        format,
+        templates: args.templates.clone().or_else(|| config.templates.clone()),
+        csv_delimiter: args.delimiter,
+        policy: tr::BillingPolicy {
+            rounding,
+            expense_scope: tr::ExpenseScope::All,
+            midnight: args.midnight.or_else(|| profile.and_then(|p| p.midnight)).unwrap_or_default(),
+            timezone: args.timezone.or_else(|| profile.and_then(|p| p.timezone)),
+            tags: args.tags.or_else(|| profile.and_then(|p| p.tags)).unwrap_or_default(),
+            tag: args.tag.clone(),
+        },
+        self_name,
+        identity: config.identity.clone(),
+        project,
+        client,
+        client_address: profile.and_then(|p| p.address.clone()),
+        invoice_no: args.invoice_no.clone(),
+        issue_date,
+        due_date,
+        notes: args.notes.clone().or_else(|| profile.and_then(|p| p.notes.clone())),
+        show_breaks: args.show_breaks || profile.and_then(|p| p.show_breaks).unwrap_or(false),
+        order: args.order.unwrap_or(DayOrder::OldestFirst),
+    };
+
+    // Check the format now rather than after the ledger is updated
+    render::renderer(&opts)?;
+
+    Ok(opts)
+}
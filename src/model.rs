@@ -0,0 +1,410 @@
+// The typed worklog: a list of days, each holding the entries written
+// under its `# YYYY-MM-DD` heading and the clocked sessions paired up
+// from them.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+use chrono::{DateTime, NaiveDate, TimeZone};
+use chrono_tz::Tz;
+
+#[derive(Debug, Clone, Default)]
+pub struct Worklog {
+    // Oldest first, whichever way the file has them. A date headed in
+    // several files is one day; one headed twice in a file is two, in
+    // file order.
+    pub days: Vec<Day>,
+    // How the file has them
+    pub order: DayOrder,
+    // Clock-ins not yet clocked out, oldest first
+    pub open_sessions: Vec<OpenSession>,
+}
+
+// Which way a worklog's headings run, or a report lists days
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DayOrder {
+    #[default]
+    NewestFirst,
+    OldestFirst,
+}
+
+#[derive(Debug, Clone)]
+pub struct Day {
+    pub date: NaiveDate,
+    // 1-based line of the heading, and one past the last line of the day
+    pub line: usize,
+    pub end_line: usize,
+    // The same date's headings in other files, each to one past its last
+    // line, with their entries after this heading's
+    pub parts: Vec<Range<usize>>,
+    pub entries: Vec<Entry>,
+    // Sessions clocked in on this day
+    pub sessions: Vec<Session>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub line: usize, // 1-based
+    pub kind: EntryKind,
+}
+
+#[derive(Debug, Clone)]
+pub enum EntryKind {
+    Junk(String),
+    Action(Action),
+    Time(Time),
+    Prediction(Prediction),
+    ClockIn(Clock),
+    ClockOut(Clock),
+    Expense(Expense),
+    // Time logged after the fact, as in "- 1.5h (Nervos) reviewed PR"
+    Duration(LoggedTime),
+    // Stopping and restarting the clock within a session, for the
+    // session named or every open one
+    Pause(Clock),
+    Resume(Clock),
+    // A break taken within a session, as in "- break 30m"
+    Break(LoggedTime),
+    // A `tz: Area/City` line; times from here on are in that zone
+    TimeZone(Tz),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Action {
+    pub text: String,
+    pub tags: Vec<Tag>,
+}
+
+// `#review` or `issue:1234` in an action
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Tag {
+    pub key: String,
+    pub value: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Clock {
+    pub company: Option<String>,
+    // When written on the clock line itself
+    pub time: Option<Time>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Time {
+    pub hour: u8, // 0-23
+    pub minute: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Prediction {
+    pub predicted_pleasure: u8,
+    pub predicted_pain: u8,
+    pub actual_pleasure: u8,
+    pub actual_pain: u8,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expense {
+    pub cost: f64,
+    pub what: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoggedTime {
+    pub minutes: u32,
+    pub company: Option<String>,
+    pub what: String,
+}
+
+// A matched clock-in / clock-out pair and the actions logged between.
+// The clock-out may be under a later day's heading than the clock-in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    pub company: Option<String>,
+    // The days of the clock-in and clock-out
+    pub date: NaiveDate,
+    pub end_date: NaiveDate,
+    pub start: Time,
+    pub end: Time,
+    // The time zones in effect at the clock-in and clock-out, if any
+    pub start_tz: Option<Tz>,
+    pub end_tz: Option<Tz>,
+    pub actions: Vec<Action>,
+    pub breaks: Vec<Break>,
+    // Time between timestamps within the session that actions with
+    // tags were logged in
+    pub tagged: Vec<TaggedTime>,
+    pub line: usize, // of the clock-in
+    pub end_line: usize, // of the clock-out
+}
+
+// The time worked between two timestamps, less breaks, and the tags of
+// the actions logged between them, each once
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaggedTime {
+    // The day of the later timestamp
+    pub date: NaiveDate,
+    pub minutes: u32,
+    pub tags: Vec<Tag>,
+}
+
+// A clock-in without a clock-out yet, and the breaks taken so far
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpenSession {
+    pub company: Option<String>,
+    pub date: NaiveDate,
+    // Missing if the clock-in has no timestamp
+    pub start: Option<Time>,
+    pub start_tz: Option<Tz>,
+    pub breaks: Vec<Break>,
+    // Since when, if paused now
+    pub paused: Option<(NaiveDate, Time)>,
+    pub line: usize,
+}
+
+// Time not worked within a session, from a pause and resume or a
+// `break` entry
+#[derive(Debug, Clone, PartialEq)]
+pub struct Break {
+    // The day of the pause or break entry
+    pub date: NaiveDate,
+    pub minutes: u32,
+    pub line: usize,
+}
+
+impl Worklog {
+    pub fn day(&self, date: NaiveDate) -> Option<&Day> {
+        self.days.iter().find(|d| d.date == date)
+    }
+
+    // Days from `start` through `end`, inclusive, oldest first
+    pub fn days_between(&self, start: NaiveDate, end: NaiveDate) -> impl Iterator<Item = &Day> {
+        self.days.iter().filter(move |d| d.date >= start && d.date <= end)
+    }
+
+    pub fn sessions(&self) -> impl Iterator<Item = &Session> {
+        self.days.iter().flat_map(|d| d.sessions.iter())
+    }
+
+    // The sessions open at `line`, which may have been clocked in on an
+    // earlier day
+    pub fn sessions_at(&self, line: usize) -> impl Iterator<Item = &Session> {
+        let at = self.position(line);
+        // A line under a heading with no clock lines, such as in a file
+        // of expenses, is in every session clocked that day
+        let day = self.days.iter().find(|d| d.contains_line(line)).filter(|d| !d.clocked(d.part(line)));
+        self.sessions().filter(move |s| match day {
+            Some(day) => s.date <= day.date && day.date <= s.end_date,
+            None => at.is_some() && self.position(s.line) < at && at < self.position(s.end_line),
+        })
+    }
+
+    // The lines of a session: from the clock-in to the end of its day,
+    // then each later day from its heading, up to the clock-out. Running
+    // newest first, the clock-out is on an earlier line than the clock-in.
+    pub fn session_lines(&self, session: &Session) -> Vec<usize> {
+        let first = self.days.iter().position(|d| d.contains_line(session.line));
+        let last = self.days.iter().position(|d| d.contains_line(session.end_line));
+        match (first, last) {
+            (Some(first), Some(last)) if first <= last => {
+                self.days[first ..= last].iter().flat_map(|d| d.ranges()).flat_map(|part| {
+                    let from = if part.contains(&session.line) { session.line } else { part.start };
+                    let to = if part.contains(&session.end_line) { session.end_line } else { part.end - 1 };
+                    from ..= to
+                }).collect()
+            }
+            _ => (session.line ..= session.end_line).collect(),
+        }
+    }
+
+    // Where a line comes in time: the index of its day, and the line.
+    // A worklog running newest first has later days on earlier lines.
+    fn position(&self, line: usize) -> Option<(usize, usize)> {
+        self.days.iter().position(|d| d.contains_line(line)).map(|i| (i, line))
+    }
+}
+
+impl Day {
+    pub fn expenses(&self) -> impl Iterator<Item = &Expense> {
+        self.entries.iter().filter_map(|e| {
+            match e.kind {
+                EntryKind::Expense(ref expense) => Some(expense),
+                _ => None,
+            }
+        })
+    }
+
+    pub fn durations(&self) -> impl Iterator<Item = (usize, &LoggedTime)> {
+        self.entries.iter().filter_map(|e| {
+            match e.kind {
+                EntryKind::Duration(ref duration) => Some((e.line, duration)),
+                _ => None,
+            }
+        })
+    }
+
+    pub fn contains_line(&self, line: usize) -> bool {
+        self.ranges().any(|r| r.contains(&line))
+    }
+
+    // The lines of each heading of the day and its entries
+    pub fn ranges(&self) -> impl Iterator<Item = Range<usize>> + '_ {
+        Some(self.line .. self.end_line).into_iter().chain(self.parts.iter().cloned())
+    }
+
+    // The lines of the heading `line` is under
+    fn part(&self, line: usize) -> Range<usize> {
+        self.ranges().find(|r| r.contains(&line)).unwrap_or(self.line .. self.end_line)
+    }
+
+    // Whether any session is clocked in or out within `lines`
+    fn clocked(&self, lines: Range<usize>) -> bool {
+        self.entries.iter().any(|e| {
+            lines.contains(&e.line) && matches!(e.kind, EntryKind::ClockIn(..) | EntryKind::ClockOut(..))
+        })
+    }
+}
+
+impl Tag {
+    // Whether this is the tag `filter`, or has its key when it has no
+    // value
+    pub fn matches(&self, filter: &Tag) -> bool {
+        self.key.eq_ignore_ascii_case(&filter.key) &&
+            (filter.value.is_none() || self.value == filter.value)
+    }
+}
+
+// "review" or "issue:1234"
+impl fmt::Display for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.value {
+            Some(ref value) => write!(f, "{}:{}", self.key, value),
+            None => write!(f, "{}", self.key),
+        }
+    }
+}
+
+// As written in an action or without the '#'
+impl FromStr for Tag {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Tag, String> {
+        let s = s.strip_prefix('#').unwrap_or(s);
+        let (key, value) = match s.find(':') {
+            Some(i) => (&s[..i], Some(s[i + 1..].to_string())),
+            None => (s, None),
+        };
+        if key.is_empty() || value.as_ref().is_some_and(|v| v.is_empty()) {
+            return Err(format!("'{}' is not a tag; expected e.g. 'review' or 'issue:1234'", s));
+        }
+        Ok(Tag { key: key.to_string(), value })
+    }
+}
+
+impl FromStr for DayOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<DayOrder, String> {
+        match s {
+            "newest" => Ok(DayOrder::NewestFirst),
+            "oldest" => Ok(DayOrder::OldestFirst),
+            _ => Err(format!("expected newest or oldest, not '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for DayOrder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DayOrder::NewestFirst => write!(f, "newest to oldest"),
+            DayOrder::OldestFirst => write!(f, "oldest to newest"),
+        }
+    }
+}
+
+impl Time {
+    pub fn new(hour: u8, minute: u8) -> Time {
+        Time { hour, minute }
+    }
+
+    pub fn minute_of_day(&self) -> u32 {
+        (self.hour as u32) * 60 + (self.minute as u32)
+    }
+}
+
+// Written the way the worklog writes it, e.g. "9:05 AM"
+impl fmt::Display for Time {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (hour, am_pm) = match self.hour {
+            0 => (12, "AM"),
+            h @ 1 ..= 11 => (h, "AM"),
+            12 => (12, "PM"),
+            h => (h - 12, "PM"),
+        };
+        write!(f, "{}:{:02} {}", hour, self.minute, am_pm)
+    }
+}
+
+impl Session {
+    // Time worked: the time clocked less breaks
+    pub fn minutes(&self) -> u32 {
+        self.elapsed_minutes().saturating_sub(self.break_minutes())
+    }
+
+    // From clock-in to clock-out
+    pub fn elapsed_minutes(&self) -> u32 {
+        elapsed((self.date, self.start, self.start_tz), (self.end_date, self.end, self.end_tz))
+    }
+
+    pub fn break_minutes(&self) -> u32 {
+        self.breaks.iter().map(|b| b.minutes).sum()
+    }
+
+    // Time between timestamps by the tags of the actions logged between
+    pub fn tag_minutes(&self) -> BTreeMap<Tag, u32> {
+        let mut minutes = BTreeMap::new();
+        for time in &self.tagged {
+            for tag in &time.tags {
+                *minutes.entry(tag.clone()).or_insert(0) += time.minutes;
+            }
+        }
+        minutes
+    }
+
+    // The time logged with a tag matching `filter`, each stretch once
+    pub fn tagged_with<'a>(&'a self, filter: &'a Tag) -> impl Iterator<Item = &'a TaggedTime> {
+        self.tagged.iter().filter(move |t| t.tags.iter().any(|tag| tag.matches(filter)))
+    }
+
+    pub fn start_instant(&self) -> Option<DateTime<Tz>> {
+        self.start_tz.and_then(|tz| instant(tz, self.date, self.start))
+    }
+
+    pub fn end_instant(&self) -> Option<DateTime<Tz>> {
+        self.end_tz.and_then(|tz| instant(tz, self.end_date, self.end))
+    }
+}
+
+// Minutes between two wall-clock times, which with time zones accounts
+// for DST changes and travel
+pub fn elapsed(from: (NaiveDate, Time, Option<Tz>), to: (NaiveDate, Time, Option<Tz>)) -> u32 {
+    let (start_date, start, start_tz) = from;
+    let (end_date, end, end_tz) = to;
+    let instants = (start_tz.and_then(|tz| instant(tz, start_date, start)),
+                    end_tz.and_then(|tz| instant(tz, end_date, end)));
+    if let (Some(start), Some(end)) = instants {
+        return end.signed_duration_since(start).num_minutes().max(0) as u32;
+    }
+    let days = end_date.signed_duration_since(start_date).num_days().max(0) as u32;
+    (days * MINUTES_PER_DAY + end.minute_of_day()).saturating_sub(start.minute_of_day())
+}
+
+// A wall-clock time in `tz`. Times repeated when clocks go back are
+// taken as the first; times skipped when they go forward don't exist.
+pub fn instant(tz: Tz, date: NaiveDate, time: Time) -> Option<DateTime<Tz>> {
+    let local = date.and_hms_opt(time.hour as u32, time.minute as u32, 0)?;
+    tz.from_local_datetime(&local).earliest()
+}
+
+pub const MINUTES_PER_DAY: u32 = 24 * 60;
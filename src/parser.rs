@@ -0,0 +1,933 @@
+// Turning worklog text into a `Worklog`.
+//
+// Each line is first classified on its own by `line_to_raw_entry`, then
+// the entries are grouped under their day headings. Worklogs may run
+// newest to oldest or oldest to newest; which is worked out from the
+// headings, and the days put in date order. Then the clock-ins and
+// clock-outs are paired into sessions, which can run past midnight into
+// the next day.
+
+use std::collections::BTreeMap;
+use std::mem;
+use regex::Regex;
+use chrono::{LocalResult, NaiveDate, TimeZone};
+use chrono_tz::Tz;
+
+use diagnostics::{Diagnostics, LineDiagnostics};
+use model::*;
+use sources::Sources;
+
+// Which timestamp formats are recognized
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TimeFormat {
+    // "9:30 AM", "9:30am", "9:30 a.m.", "9:30:15 PM"
+    #[serde(rename = "12h")]
+    TwelveHour,
+    // "09:30", "21:30", "21:30:15"
+    #[serde(rename = "24h")]
+    TwentyFourHour,
+}
+
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    pub time_formats: Vec<TimeFormat>,
+    // The zone in effect before any `tz:` line. Without one, times are
+    // taken as they are written, ignoring DST changes.
+    pub timezone: Option<Tz>,
+}
+
+// Only 12-hour times by default, as a bullet like "- 3:2 odds" or
+// "- 10:30 standup" would otherwise be taken for a timestamp
+impl Default for ParseOptions {
+    fn default() -> ParseOptions {
+        ParseOptions {
+            time_formats: vec![TimeFormat::TwelveHour],
+            timezone: None,
+        }
+    }
+}
+
+// What a single line represents, before it is placed in a day
+#[derive(Debug, Clone)]
+pub enum RawEntry {
+    NewDay(NaiveDate),
+    Entry(EntryKind),
+    // Another file to read, see `sources`
+    Include(String),
+}
+
+pub fn parse_worklog(path: &str, text: &str) -> (Worklog, Diagnostics) {
+    parse_worklog_with(path, text, &ParseOptions::default())
+}
+
+pub fn parse_worklog_with(path: &str, text: &str, opts: &ParseOptions) -> (Worklog, Diagnostics) {
+    let mut sources = Sources::new();
+    sources.add(path, text.to_string());
+    parse_sources(&sources, opts)
+}
+
+// Parse a worklog split across files, merging their days
+pub fn parse_sources(sources: &Sources, opts: &ParseOptions) -> (Worklog, Diagnostics) {
+    let lines = sources.lines();
+    let mut diags = Diagnostics::new();
+    let mut days: Vec<Day> = vec![];
+
+    for file in sources.files() {
+        let first_day = days.len();
+        for (i, text) in file.text.lines().enumerate() {
+            let line = file.line_number(i + 1);
+            let raw = line_to_raw_entry(text, opts, &mut diags.line(&file.path, i + 1, text));
+            match raw {
+                RawEntry::NewDay(date) => {
+                    days.push(Day {
+                        date,
+                        line,
+                        end_line: line + 1,
+                        parts: vec![],
+                        entries: vec![],
+                        sessions: vec![],
+                    });
+                }
+                RawEntry::Entry(kind) => {
+                    match days[first_day..].last_mut() {
+                        Some(day) => {
+                            day.entries.push(Entry { line, kind });
+                            day.end_line = line + 1;
+                        }
+                        None => {
+                            // Prose before the first heading is fine, but
+                            // anything that would be billed is lost
+                            match kind {
+                                EntryKind::Junk(..) | EntryKind::Action(..) => { }
+                                _ => {
+                                    diags.line(&file.path, i + 1, text)
+                                        .warning(0 .. text.len(),
+                                                 "entry before the first date heading is ignored".to_string());
+                                }
+                            }
+                        }
+                    }
+                }
+                RawEntry::Include(..) => { }
+            }
+        }
+    }
+
+    let order = check_order(sources, &lines, &days, &mut diags);
+    days.sort_by_key(|d| d.date);
+    let mut days = merge_files(sources, &lines, days, &mut diags);
+
+    let open_sessions = pair_sessions(sources, &lines, &mut days, opts.timezone, &mut diags);
+
+    (Worklog { days, order, open_sessions }, diags)
+}
+
+// Which way the headings run, by how most of them follow each other.
+// Each file is checked against its own order: a heading that breaks it
+// is an error, but a file running both ways throughout only gets
+// warnings, as there is no telling which headings are out of place.
+fn check_order(sources: &Sources, lines: &[&str], days: &[Day], diags: &mut Diagnostics) -> DayOrder {
+    let order = |pairs: &[(&Day, &Day)]| {
+        let newest_first = pairs.iter().filter(|&&(a, b)| b.date < a.date).count();
+        let oldest_first = pairs.iter().filter(|&&(a, b)| b.date > a.date).count();
+        if oldest_first > newest_first { DayOrder::OldestFirst } else { DayOrder::NewestFirst }
+    };
+
+    // Pairs of successive headings in the same file
+    let pairs: Vec<(&Day, &Day)> = days.windows(2)
+        .filter(|w| sources.locate(w[0].line).0.path == sources.locate(w[1].line).0.path)
+        .map(|w| (&w[0], &w[1]))
+        .collect();
+
+    for file in sources.files() {
+        let in_file: Vec<(&Day, &Day)> = pairs.iter().cloned()
+            .filter(|&(a, _)| sources.locate(a.line).0.path == file.path)
+            .collect();
+        let file_order = order(&in_file);
+        let backwards: Vec<(&Day, &Day)> = in_file.into_iter().filter(|&(prev, day)| match file_order {
+            DayOrder::NewestFirst => day.date > prev.date,
+            DayOrder::OldestFirst => day.date < prev.date,
+        }).collect();
+        let mixed = backwards.len() > 1;
+        for (prev, day) in backwards {
+            let source = lines[day.line - 1];
+            let message = format!("heading out of order: {} comes after {}, but the file runs {}",
+                                  day.date, prev.date, file_order);
+            let mut diag = sources.diag(diags, day.line);
+            if mixed {
+                diag.warning(body_span(source), message);
+            } else {
+                diag.error(body_span(source), message);
+            }
+        }
+    }
+    order(&pairs)
+}
+
+// Fold the days of a date headed in several files into the first, so
+// that a file of expenses is billed with the sessions clocked in
+// another. Clock lines in both are paired as if one file followed the
+// other, which is reported. `days` are sorted by date.
+fn merge_files(sources: &Sources, lines: &[&str], days: Vec<Day>, diags: &mut Diagnostics) -> Vec<Day> {
+    let path = |line: usize| &sources.locate(line).0.path;
+    let clocked = |day: &Day| day.entries.iter().any(|e| {
+        matches!(e.kind, EntryKind::ClockIn(..) | EntryKind::ClockOut(..))
+    });
+
+    let mut merged: Vec<Day> = vec![];
+    for day in days {
+        let into = merged.iter_mut().rev().take_while(|d| d.date == day.date)
+            .find(|d| d.ranges().all(|r| path(r.start) != path(day.line)));
+        match into {
+            Some(into) => {
+                if clocked(into) && clocked(&day) {
+                    let source = lines[day.line - 1];
+                    sources.diag(diags, day.line)
+                        .warning(body_span(source), format!("{} is also a heading in {}, and both clock \
+                                                             sessions; their clock lines are paired as if \
+                                                             one file followed the other",
+                                                            day.date, sources.describe(into.line)));
+                }
+                into.parts.push(day.line .. day.end_line);
+                into.entries.extend(day.entries);
+            }
+            None => merged.push(day),
+        }
+    }
+    merged
+}
+
+// Span of the whole line after its `# ` or `- ` marker
+pub fn body_span(line: &str) -> ::std::ops::Range<usize> {
+    if line.starts_with("# ") || line.starts_with("- ") {
+        2 .. line.len()
+    } else {
+        0 .. line.len()
+    }
+}
+
+// A clock-in must be followed by its timestamp, and a clock-out
+// preceded by one, both under the same heading, unless they carry their
+// own. Sessions are added to
+// the day they were clocked in on. A `tz:` line holds from where it is
+// written into the following days, until the next one.
+fn pair_sessions(sources: &Sources, lines: &[&str], days: &mut [Day], timezone: Option<Tz>,
+                 diags: &mut Diagnostics) -> Vec<OpenSession> {
+    struct Open {
+        day: usize,
+        date: NaiveDate,
+        line: usize,
+        start: Option<Time>,
+        tz: Option<Tz>,
+        actions: Vec<Action>,
+        breaks: Vec<Break>,
+        paused: Option<Paused>,
+        // The last timestamp, and the tags of the actions and the
+        // `break` minutes since
+        mark: Option<(NaiveDate, Time, Option<Tz>)>,
+        tags: Vec<Tag>,
+        break_minutes: u32,
+        tagged: Vec<TaggedTime>,
+    }
+
+    impl Open {
+        // Attribute the time worked since the last timestamp to the
+        // tags of the actions logged since
+        fn mark(&mut self, at: (NaiveDate, Time, Option<Tz>)) {
+            if let Some(from) = self.mark {
+                if !self.tags.is_empty() {
+                    let minutes = elapsed(from, at).saturating_sub(self.break_minutes);
+                    let tags = self.tags.drain(..).collect();
+                    self.tagged.push(TaggedTime { date: at.0, minutes, tags });
+                }
+            }
+            self.tags.clear();
+            self.break_minutes = 0;
+            self.mark = Some(at);
+        }
+    }
+
+    struct Paused {
+        date: NaiveDate,
+        line: usize,
+        time: Option<Time>,
+        tz: Option<Tz>,
+    }
+
+    // The open sessions a pause, resume or break applies to
+    fn sessions_for<'a>(open: &'a mut BTreeMap<Option<String>, Open>,
+                        company: &Option<String>) -> Vec<&'a mut Open> {
+        open.iter_mut()
+            .filter(|&(c, _)| company.is_none() || c == company)
+            .map(|(_, session)| session)
+            .collect()
+    }
+
+    let mut timezone = timezone;
+
+    let mut open: BTreeMap<Option<String>, Open> = BTreeMap::new();
+    let mut sessions = vec![];
+
+    for (d, day) in days.iter().enumerate() {
+        let entries = &day.entries;
+        let time_at = |i: usize| {
+            match entries.get(i) {
+                Some(&Entry { kind: EntryKind::Time(t), .. }) => Some(t),
+                _ => None,
+            }
+        };
+
+        for (i, entry) in entries.iter().enumerate() {
+            let source = lines[entry.line - 1];
+            let span = body_span(source);
+            match entry.kind {
+                EntryKind::ClockIn(Clock { company: ref c, time }) => {
+                    let mut diag = sources.diag(diags, entry.line);
+                    if let Some(prev) = open.get(c) {
+                        diag.error(span.clone(), format!("clock-in {}while already clocked in on {}",
+                                                         describe(c), sources.line_ref(prev.line, entry.line)));
+                    }
+                    let start = time.or_else(|| time_at(i + 1));
+                    match start {
+                        Some(start) => check_local_time(&mut diag, span, timezone, day.date, start),
+                        None => diag.error(span, "clock-in not followed by timestamp".to_string()),
+                    }
+                    open.insert(c.clone(), Open {
+                        day: d,
+                        date: day.date,
+                        line: entry.line,
+                        start,
+                        tz: timezone,
+                        actions: vec![],
+                        breaks: vec![],
+                        paused: None,
+                        mark: start.map(|start| (day.date, start, timezone)),
+                        tags: vec![],
+                        break_minutes: 0,
+                        tagged: vec![],
+                    });
+                }
+                EntryKind::ClockOut(Clock { company: ref c, time }) => {
+                    let mut diag = sources.diag(diags, entry.line);
+                    let end = time.or_else(|| i.checked_sub(1).and_then(time_at));
+                    match end {
+                        Some(end) => check_local_time(&mut diag, span.clone(), timezone, day.date, end),
+                        None => diag.error(span.clone(), "clock-out not preceded by timestamp".to_string()),
+                    }
+                    let closed = open.remove(c).map(|mut session| {
+                        if let Some(end) = end {
+                            session.mark((day.date, end, timezone));
+                        }
+                        session
+                    });
+                    match closed {
+                        Some(Open { day: start_day, date, line, start: Some(start), tz, actions, breaks, paused,
+                                    tagged, .. }) => {
+                            if let Some(paused) = paused {
+                                diag.error(span.clone(), format!("clock-out while paused since {}; \
+                                                                  resume first", sources.line_ref(paused.line, entry.line)));
+                            }
+                            if let Some(end) = end {
+                                if date == day.date && end <= start {
+                                    diag.error(span, "clock-out is not later than clock-in; to work past midnight, \
+                                                          clock out under the next day's heading".to_string());
+                                } else {
+                                    let session = Session {
+                                        company: c.clone(),
+                                        date,
+                                        end_date: day.date,
+                                        start,
+                                        end,
+                                        start_tz: tz,
+                                        end_tz: timezone,
+                                        actions,
+                                        breaks,
+                                        tagged,
+                                        line,
+                                        end_line: entry.line,
+                                    };
+                                    if session.break_minutes() >= session.elapsed_minutes() {
+                                        diag.error(span, format!("breaks add up to {} minutes, but only {} \
+                                                                  minutes were clocked",
+                                                                 session.break_minutes(), session.elapsed_minutes()));
+                                    }
+                                    sessions.push((start_day, session));
+                                }
+                            }
+                        }
+                        Some(_) => { }
+                        None => {
+                            diag.error(span, format!("clock-out {}without clock-in", describe(c)));
+                        }
+                    }
+                }
+                EntryKind::Pause(Clock { company: ref c, time }) => {
+                    let mut diag = sources.diag(diags, entry.line);
+                    let at = time.or_else(|| i.checked_sub(1).and_then(time_at));
+                    match at {
+                        Some(at) => check_local_time(&mut diag, span.clone(), timezone, day.date, at),
+                        None => diag.error(span.clone(), "pause not preceded by timestamp".to_string()),
+                    }
+                    let targets = sessions_for(&mut open, c);
+                    if targets.is_empty() {
+                        diag.error(span.clone(), format!("pause {}outside a clocked session", describe(c)));
+                    }
+                    for session in targets {
+                        if let Some(ref paused) = session.paused {
+                            diag.error(span.clone(), format!("pause while already paused since {}",
+                                                             sources.line_ref(paused.line, entry.line)));
+                            continue;
+                        }
+                        if let Some(at) = at {
+                            session.mark((day.date, at, timezone));
+                        }
+                        session.paused = Some(Paused { date: day.date, line: entry.line, time: at, tz: timezone });
+                    }
+                }
+                EntryKind::Resume(Clock { company: ref c, time }) => {
+                    let mut diag = sources.diag(diags, entry.line);
+                    let at = time.or_else(|| time_at(i + 1));
+                    match at {
+                        Some(at) => check_local_time(&mut diag, span.clone(), timezone, day.date, at),
+                        None => diag.error(span.clone(), "resume not followed by timestamp".to_string()),
+                    }
+                    let mut resumed = false;
+                    for session in sessions_for(&mut open, c) {
+                        let paused = match session.paused.take() {
+                            Some(paused) => paused,
+                            None => continue,
+                        };
+                        resumed = true;
+                        // Time for tags counts again from here
+                        session.mark = at.map(|at| (day.date, at, timezone));
+                        session.tags.clear();
+                        if let (Some(from), Some(to)) = (paused.time, at) {
+                            if paused.date == day.date && to < from {
+                                diag.error(span.clone(), format!("resume is earlier than the pause on {}",
+                                                                 sources.line_ref(paused.line, entry.line)));
+                                continue;
+                            }
+                            session.breaks.push(Break {
+                                date: paused.date,
+                                minutes: elapsed((paused.date, from, paused.tz), (day.date, to, timezone)),
+                                line: paused.line,
+                            });
+                        }
+                    }
+                    if !resumed {
+                        diag.error(span, format!("resume {}without pause", describe(c)));
+                    }
+                }
+                EntryKind::Break(LoggedTime { ref company, minutes, .. }) => {
+                    let targets = sessions_for(&mut open, company);
+                    if targets.is_empty() {
+                        sources.diag(diags, entry.line)
+                            .error(span, format!("break {}outside a clocked session", describe(company)));
+                    }
+                    for session in targets {
+                        session.breaks.push(Break { date: day.date, minutes, line: entry.line });
+                        session.break_minutes += minutes;
+                    }
+                }
+                EntryKind::Action(ref action) => {
+                    // What was done on a break isn't billed
+                    for session in open.values_mut().filter(|s| s.paused.is_none()) {
+                        session.actions.push(action.clone());
+                        for tag in &action.tags {
+                            if !session.tags.contains(tag) {
+                                session.tags.push(tag.clone());
+                            }
+                        }
+                    }
+                }
+                EntryKind::Time(time) => {
+                    for session in open.values_mut() {
+                        session.mark((day.date, time, timezone));
+                    }
+                }
+                EntryKind::TimeZone(tz) => {
+                    timezone = Some(tz);
+                }
+                _ => { }
+            }
+        }
+    }
+
+    // Left open, which is an error unless clocked in on the newest day,
+    // when it may still be going
+    let newest = days.last().map(|d| d.date);
+    let mut still_open = vec![];
+    for (c, session) in mem::take(&mut open) {
+        let source = lines[session.line - 1];
+        let mut diag = sources.diag(diags, session.line);
+        if Some(session.date) == newest {
+            diag.warning(body_span(source), format!("clock-in {}without clock-out yet; \
+                                                     not billed until clocked out", describe(&c)));
+        } else {
+            diag.error(body_span(source), format!("clock-in {}without clock-out", describe(&c)));
+        }
+        still_open.push(OpenSession {
+            company: c,
+            date: session.date,
+            start: session.start,
+            start_tz: session.tz,
+            breaks: session.breaks,
+            paused: session.paused.and_then(|p| p.time.map(|time| (p.date, time))),
+            line: session.line,
+        });
+    }
+    still_open.sort_by_key(|s| (s.date, s.start, s.line));
+
+    for (d, session) in sessions {
+        days[d].sessions.push(session);
+    }
+    still_open
+}
+
+// Clock times that DST makes ambiguous or skips over
+fn check_local_time(diag: &mut LineDiagnostics, span: ::std::ops::Range<usize>,
+                    tz: Option<Tz>, date: NaiveDate, time: Time) {
+    let tz = match tz {
+        Some(tz) => tz,
+        None => return,
+    };
+    let local = date.and_hms_opt(time.hour as u32, time.minute as u32, 0).expect("");
+    match tz.from_local_datetime(&local) {
+        LocalResult::Single(_) => { }
+        LocalResult::Ambiguous(..) => {
+            diag.warning(span, format!("{} happens twice on {} in {}, as clocks go back; \
+                                        taking the first", time, date, tz));
+        }
+        LocalResult::None => {
+            diag.error(span, format!("{} doesn't exist on {} in {}, as clocks go forward", time, date, tz));
+        }
+    }
+}
+
+// "for 'Nervos' ", for messages about a company's clock lines
+pub fn describe(company: &Option<String>) -> String {
+    match *company {
+        Some(ref c) => format!("for '{}' ", c),
+        None => String::new(),
+    }
+}
+
+// Determine what each individual line represents
+pub fn line_to_raw_entry(line: &str, opts: &ParseOptions, diag: &mut LineDiagnostics) -> RawEntry {
+    let line_lcase = line.to_ascii_lowercase();
+
+    let clock = |diag: &mut LineDiagnostics| {
+        let time = parse_clock_time(line, opts, diag)?;
+        Ok(Clock { company: parse_company(line), time })
+    };
+
+    let junk = || RawEntry::Entry(EntryKind::Junk(line.to_string()));
+
+    if let Some(idx) = line_lcase.find("clockin") {
+        diag.error(idx .. idx + "clockin".len(),
+                   "use 'clock in', not 'clockin'".to_string());
+        return clock(diag).map(|c| RawEntry::Entry(EntryKind::ClockIn(c))).unwrap_or_else(|()| junk());
+    }
+
+    if let Some(idx) = line_lcase.find("clockout") {
+        diag.error(idx .. idx + "clockout".len(),
+                   "use 'clock out', not 'clockout'".to_string());
+        return clock(diag).map(|c| RawEntry::Entry(EntryKind::ClockOut(c))).unwrap_or_else(|()| junk());
+    }
+
+    if line_lcase.contains("clock in") {
+        return clock(diag).map(|c| RawEntry::Entry(EntryKind::ClockIn(c))).unwrap_or_else(|()| junk());
+    }
+
+    if line_lcase.contains("clock out") {
+        return clock(diag).map(|c| RawEntry::Entry(EntryKind::ClockOut(c))).unwrap_or_else(|()| junk());
+    }
+
+    match pause_keyword(line) {
+        Some(Pause::Pause) => {
+            return clock(diag).map(|c| RawEntry::Entry(EntryKind::Pause(c))).unwrap_or_else(|()| junk());
+        }
+        Some(Pause::Resume) => {
+            return clock(diag).map(|c| RawEntry::Entry(EntryKind::Resume(c))).unwrap_or_else(|()| junk());
+        }
+        None => { }
+    }
+
+    if let Some(path) = include_path(line) {
+        return RawEntry::Include(path.to_string());
+    }
+
+    match parse_timezone(line, diag) {
+        Ok(Some(tz)) => return RawEntry::Entry(EntryKind::TimeZone(tz)),
+        Ok(None) => { }
+        Err(()) => return junk(),
+    }
+
+    if let Some(rest) = line.strip_prefix("# ") {
+        match parse_date(rest, 2, diag) {
+            Ok(Some(date)) => return RawEntry::NewDay(date),
+            Ok(None) => { }
+            Err(()) => return junk(),
+        }
+    }
+
+    let rest = match line.strip_prefix("- ") {
+        Some(rest) => rest,
+        None => return junk(),
+    };
+
+    let text = rest.trim();
+    // Column of `text` within the full line, for diagnostics
+    let col = 2 + (rest.len() - rest.trim_start().len());
+
+    match parse_time(text, col, &opts.time_formats, diag) {
+        Ok(Some(time)) => return RawEntry::Entry(EntryKind::Time(time)),
+        Ok(None) => { }
+        Err(()) => return junk(),
+    }
+
+    match parse_prediction(text, col, diag) {
+        Ok(Some(pred)) => return RawEntry::Entry(EntryKind::Prediction(pred)),
+        Ok(None) => { }
+        Err(()) => return junk(),
+    }
+
+    match parse_expense(text, col, diag) {
+        Ok(Some(expense)) => return RawEntry::Entry(EntryKind::Expense(expense)),
+        Ok(None) => { }
+        Err(()) => return junk(),
+    }
+
+    match parse_break(text, col, diag) {
+        Ok(Some(duration)) => return RawEntry::Entry(EntryKind::Break(duration)),
+        Ok(None) => { }
+        Err(()) => return junk(),
+    }
+
+    match parse_duration(text, col, false, diag) {
+        Ok(Some(duration)) => return RawEntry::Entry(EntryKind::Duration(duration)),
+        Ok(None) => { }
+        Err(()) => return junk(),
+    }
+
+    let tags = tag_spans(text).into_iter().map(|(_, tag)| tag).collect();
+    RawEntry::Entry(EntryKind::Action(Action { text: text.to_string(), tags }))
+}
+
+// `#tag` and `key:value` tokens and where they are in `text`. Tags
+// start with a letter, so "PR #123", "10:30" and URLs aren't tags.
+pub fn tag_spans(text: &str) -> Vec<(::std::ops::Range<usize>, Tag)> {
+    lazy_static! {
+        static ref REGEX: Regex = Regex::new(
+            r"(?:^|\s)(?:#([A-Za-z][\w-]*)|([A-Za-z][\w-]*):(\w[\w./#-]*))").expect("");
+    }
+    let mut tags = vec![];
+    for caps in REGEX.captures_iter(text) {
+        let (key, value) = match (caps.get(1), caps.get(2), caps.get(3)) {
+            (Some(key), _, _) => (key, None),
+            (None, Some(key), Some(value)) => {
+                // Trailing punctuation ends a sentence, not the value
+                let trimmed = value.as_str().trim_end_matches(|c: char| !c.is_alphanumeric());
+                (key, Some((value.start(), trimmed)))
+            }
+            _ => continue,
+        };
+        let start = if caps.get(1).is_some() { key.start() - 1 } else { key.start() };
+        let end = value.map_or(key.end(), |(start, v)| start + v.len());
+        let ends_token = text[end..].chars().next().is_none_or(|c| {
+            c.is_whitespace() || ",.;:!?)".contains(c)
+        });
+        if ends_token {
+            tags.push((start .. end, Tag {
+                key: key.as_str().to_string(),
+                value: value.map(|(_, v)| v.to_string()),
+            }));
+        }
+    }
+    tags
+}
+
+// A timestamp written on the clock line itself, as in "- 9:00 AM clock
+// in (Nervos)" or "- clock out 5:30 PM (Nervos)". Without one, the
+// time is taken from the bullet after a clock-in or before a clock-out.
+fn parse_clock_time(line: &str, opts: &ParseOptions, diag: &mut LineDiagnostics) -> ParseResult<Time> {
+    let company = match (line.rfind('('), line.rfind(')')) {
+        (Some(open), Some(close)) if open < close => open .. close,
+        _ => line.len() .. line.len(),
+    };
+    let bytes = line.as_bytes();
+    for i in 0 .. line.len() {
+        let word_start = i == 0 || bytes[i - 1].is_ascii_whitespace();
+        if word_start && bytes[i].is_ascii_digit() && !company.contains(&i) {
+            if let Some(time) = parse_time(&line[i..], i, &opts.time_formats, diag)? {
+                return Ok(Some(time));
+            }
+        }
+    }
+    Ok(None)
+}
+
+enum Pause {
+    Pause,
+    Resume,
+}
+
+// A bullet of just "pause" or "resume", with optionally a time and the
+// company, as in "- pause 12:15 PM (Nervos)". Anything more, as in
+// "- paused the deploy", is an action.
+fn pause_keyword(line: &str) -> Option<Pause> {
+    lazy_static! {
+        static ref REGEX: Regex = Regex::new(
+            r"^- *(?:\d[\d:]* ?(?:[AaPp]\.?[Mm]\.?)? +)?(?i:(pause|resume))(?: +\d[\d:]* ?(?:[AaPp]\.?[Mm]\.?)?)? *(?:\([^)]*\))? *$").expect("");
+    }
+    REGEX.captures(line).map(|caps| {
+        if caps[1].eq_ignore_ascii_case("pause") { Pause::Pause } else { Pause::Resume }
+    })
+}
+
+// `include: expenses.md` on a line of its own
+pub fn include_path(line: &str) -> Option<&str> {
+    lazy_static! {
+        static ref REGEX: Regex = Regex::new(r"^(?i:include): *(\S.*?) *$").expect("");
+    }
+    REGEX.captures(line).map(|caps| caps.get(1).expect("").as_str())
+}
+
+// `tz: America/Los_Angeles`, either alone or as a bullet
+fn parse_timezone(line: &str, diag: &mut LineDiagnostics) -> ParseResult<Tz> {
+    lazy_static! {
+        static ref REGEX: Regex = Regex::new(r"^(?:- )?(?i:tz): *(\S+) *$").expect("");
+    }
+    if let Some(caps) = REGEX.captures(line) {
+        let m = caps.get(1).expect("");
+        match m.as_str().parse() {
+            Ok(tz) => Ok(Some(tz)),
+            Err(_) => {
+                diag.error(m.start() .. m.end(), format!("unknown time zone '{}'", m.as_str()));
+                Err(())
+            }
+        }
+    } else {
+        Ok(None)
+    }
+}
+
+fn parse_company(line: &str) -> Option<String> {
+    let open_paren_idx = line.rfind('(');
+    let close_paren_idx = line.rfind(')');
+    match (open_paren_idx, close_paren_idx) {
+        (Some(open_paren_idx), Some(close_paren_idx)) => {
+            let region = &line[open_paren_idx + 1 .. close_paren_idx];
+            let region = region.trim();
+            Some(region.to_string())
+        }
+        _ => None,
+    }
+}
+
+// The following parsers return `Ok(None)` when the text isn't the kind
+// of entry they look for, and `Err(())` when it is but is malformed, in
+// which case the problem has already been reported to `diag`. `col` is
+// the offset of `s` within the source line.
+
+type ParseResult<T> = ::std::result::Result<Option<T>, ()>;
+
+// Anything may follow the date, e.g. a weekday name
+fn parse_date(s: &str, col: usize, diag: &mut LineDiagnostics) -> ParseResult<NaiveDate> {
+    lazy_static! {
+        static ref REGEX: Regex = Regex::new(r"^(\d{4})-(\d{2})-(\d{2})").expect("");
+    }
+    if let Some(m) = REGEX.find(s) {
+        match NaiveDate::parse_from_str(m.as_str(), "%Y-%m-%d") {
+            Ok(date) => Ok(Some(date)),
+            Err(e) => {
+                diag.error(col + m.start() .. col + m.end(),
+                           format!("invalid date: {}", e));
+                Err(())
+            }
+        }
+    } else {
+        Ok(None)
+    }
+}
+
+// Seconds are accepted but dropped. Something that looks like a
+// 12-hour time is never read as a 24-hour one, even if 12-hour times
+// aren't accepted.
+fn parse_time(s: &str, col: usize, formats: &[TimeFormat], diag: &mut LineDiagnostics) -> ParseResult<Time> {
+    lazy_static! {
+        static ref TWELVE_HOUR: Regex =
+            Regex::new(r"^(\d{1,2}):(\d{2})(?::(\d{2}))? ?([AaPp])\.?[Mm]\.?(?:[^A-Za-z]|$)").expect("");
+        static ref TWENTY_FOUR_HOUR: Regex =
+            Regex::new(r"^(\d{1,2}):(\d{2})(?::(\d{2}))?(?:[^\d:/]|$)").expect("");
+    }
+
+    let (caps, twelve_hour) = if let Some(caps) = TWELVE_HOUR.captures(s) {
+        if !formats.contains(&TimeFormat::TwelveHour) {
+            return Ok(None);
+        }
+        (caps, true)
+    } else if let Some(caps) = TWENTY_FOUR_HOUR.captures(s) {
+        if !formats.contains(&TimeFormat::TwentyFourHour) {
+            return Ok(None);
+        }
+        (caps, false)
+    } else {
+        return Ok(None);
+    };
+
+    let field = |i: usize| caps.get(i).map(|m| (col + m.start() .. col + m.end(), str::parse::<u8>(m.as_str())));
+    let (hour_span, hour) = field(1).expect("");
+    let (minute_span, minute) = field(2).expect("");
+
+    let max_hour = if twelve_hour { 12 } else { 23 };
+    let hour = match hour {
+        Ok(hour) if hour <= max_hour => hour,
+        _ => {
+            diag.error(hour_span, format!("hour must be at most {}, found {}", max_hour, &caps[1]));
+            return Err(());
+        }
+    };
+
+    let minute = match minute {
+        Ok(minute) if minute <= 59 => minute,
+        _ => {
+            diag.error(minute_span, format!("minute must be between 0 and 59, found {}", &caps[2]));
+            return Err(());
+        }
+    };
+
+    if let Some((second_span, second)) = field(3) {
+        match second {
+            Ok(second) if second <= 59 => { }
+            _ => {
+                diag.error(second_span, format!("second must be between 0 and 59, found {}", &caps[3]));
+                return Err(());
+            }
+        }
+    }
+
+    let hour = if twelve_hour {
+        let pm = caps[4].eq_ignore_ascii_case("p");
+        // 12 AM is midnight and 12 PM is noon
+        match (hour, pm) {
+            (12, false) => 0,
+            (12, true) => 12,
+            (hour, false) => hour,
+            (hour, true) => hour + 12,
+        }
+    } else {
+        hour
+    };
+
+    Ok(Some(Time::new(hour, minute)))
+}
+
+fn parse_prediction(s: &str, col: usize, diag: &mut LineDiagnostics) -> ParseResult<Prediction> {
+    lazy_static! {
+        static ref REGEX: Regex = Regex::new(r"^(\d+)/(\d+):(\d+)/(\d+)").expect("");
+    }
+    if let Some(caps) = REGEX.captures(s) {
+        let mut values = [0; 4];
+        for (i, value) in values.iter_mut().enumerate() {
+            match str::parse(&caps[i + 1]) {
+                Ok(v) => *value = v,
+                Err(_) => {
+                    let m = caps.get(i + 1).expect("");
+                    diag.error(col + m.start() .. col + m.end(),
+                               format!("prediction value out of range (0-255): {}", &caps[i + 1]));
+                    return Err(());
+                }
+            }
+        }
+        Ok(Some(Prediction {
+            predicted_pleasure: values[0],
+            predicted_pain: values[1],
+            actual_pleasure: values[2],
+            actual_pain: values[3],
+        }))
+    } else {
+        Ok(None)
+    }
+}
+
+// "1.5h", "2h30m" or "45m", then the company in parentheses and what was
+// done. Without the company, as in "- 5m people affected", it's prose
+// unless `company_optional`.
+fn parse_duration(s: &str, col: usize, company_optional: bool,
+                  diag: &mut LineDiagnostics) -> ParseResult<LoggedTime> {
+    lazy_static! {
+        static ref REGEX: Regex = Regex::new(
+            r"^((?:(\d+(?:\.\d+)?)h(?:(\d+)m)?|(\d+)m))(?:\s+|$)(?:\(([^)]*)\)\s*)?(.*)$").expect("");
+    }
+    let caps = match REGEX.captures(s) {
+        Some(caps) => caps,
+        None => return Ok(None),
+    };
+    if caps.get(5).is_none() && !company_optional {
+        return Ok(None);
+    }
+    let amount = caps.get(1).expect("");
+    let span = col + amount.start() .. col + amount.end();
+
+    let number = |i: usize| caps.get(i).map(|m| str::parse::<f64>(m.as_str()).expect(""));
+    if let Some(m) = caps.get(3) {
+        if number(3).expect("") >= 60.0 {
+            diag.error(col + m.start() .. col + m.end(),
+                       format!("minutes must be less than 60 after hours, found {}", m.as_str()));
+            return Err(());
+        }
+    }
+
+    let minutes = (number(2).unwrap_or(0.0) * 60.0 + number(3).unwrap_or(0.0) + number(4).unwrap_or(0.0)).round();
+    if minutes < 1.0 || minutes > MINUTES_PER_DAY as f64 {
+        diag.error(span, format!("logged time must be between 1 minute and 24 hours, found {}",
+                                 amount.as_str()));
+        return Err(());
+    }
+
+    Ok(Some(LoggedTime {
+        minutes: minutes as u32,
+        company: caps.get(5).map(|m| m.as_str().trim().to_string()),
+        what: caps[6].trim().to_string(),
+    }))
+}
+
+// "break 30m", optionally naming the company whose session it's from
+fn parse_break(s: &str, col: usize, diag: &mut LineDiagnostics) -> ParseResult<LoggedTime> {
+    let keyword = "break ";
+    if !s.to_ascii_lowercase().starts_with(keyword) {
+        return Ok(None);
+    }
+    let rest = &s[keyword.len()..];
+    let skipped = keyword.len() + rest.len() - rest.trim_start().len();
+    parse_duration(rest.trim_start(), col + skipped, true, diag)
+}
+
+fn parse_expense(s: &str, col: usize, diag: &mut LineDiagnostics) -> ParseResult<Expense> {
+    if !s.to_ascii_lowercase().starts_with("expense:") {
+        return Ok(None);
+    }
+
+    lazy_static! {
+        static ref REGEX: Regex = Regex::new(r"^Expense: *\$((?:\d|\.)*),(.*)").expect("");
+    }
+    if let Some(caps) = REGEX.captures(s) {
+        match str::parse(&caps[1]) {
+            Ok(cost) => {
+                let what: String = caps[2].to_string();
+                Ok(Some(Expense { cost, what }))
+            }
+            Err(_) => {
+                let m = caps.get(1).expect("");
+                diag.error(col + m.start() .. col + m.end(),
+                           format!("expense cost is not a number: '{}'", &caps[1]));
+                Err(())
+            }
+        }
+    } else {
+        diag.error(col .. col + s.len(),
+                   "malformed expense, expected 'Expense: $cost, description'".to_string());
+        Err(())
+    }
+}
@@ -1,15 +1,18 @@
 #![allow(unused)]
 
 use std::mem;
-use std::cell::RefCell;
+use chrono::*;
 
 use errors::*;
-use RawEntry;
+use model::{DayOrder, Worklog, EntryKind, Prediction, Time};
 
-pub fn analyze_prediction(entries: &[Entry]) -> Result<()> {
+pub fn analyze_prediction(entries: &[Entry], order: DayOrder) -> Result<()> {
 
     let stats = basic_stats(entries);
-    let weekly = weekly_stats(entries);
+    let mut weekly = weekly_stats(entries);
+    if order == DayOrder::NewestFirst {
+        weekly.reverse();
+    }
 
     println!("Pleasure predicting");
     println!("===================");
@@ -20,7 +23,7 @@ pub fn analyze_prediction(entries: &[Entry]) -> Result<()> {
     println!();
 
     for week_stats in weekly {
-        let ref stats = week_stats.stats;
+        let stats = &week_stats.stats;
         println!("{}", week_stats.week);
         println!("predictions: {}", stats.predictions);
         println!("median prediction: {} pr_pl / {} pr_pn : {} ac_pl / {} ac_pn",
@@ -69,7 +72,12 @@ fn basic_stats(entries: &[Entry]) -> BasicStats {
 
     for entry in entries {
         if let Some(ref pr) = entry.pred {
-            let Prediction(pr_pl, pr_pn, ac_pl, ac_pn) = *pr;
+            let Prediction {
+                predicted_pleasure: pr_pl,
+                predicted_pain: pr_pn,
+                actual_pleasure: ac_pl,
+                actual_pain: ac_pn,
+            } = *pr;
             predictions += 1;
             total_pr_pl += pr_pl as u64;
             total_pr_pn += pr_pn as u64;
@@ -158,7 +166,7 @@ fn weekly_stats(entries: &[Entry]) -> Vec<WeeklyStats> {
         ranges.push(idx1 .. idx2);
     }
 
-    ranges.into_iter().rev().map(|range| {
+    ranges.into_iter().map(|range| {
         let entries = &entries[range];
         let week = week_of(&entries[0].date);
         let stats = basic_stats(entries);
@@ -166,72 +174,60 @@ fn weekly_stats(entries: &[Entry]) -> Vec<WeeklyStats> {
     }).collect()
 }
 
-fn week_of(date: &str) -> String {
-    use chrono::*;
-    let default = NaiveDate::from_ymd(2017, 1, 1);
-    let dt: ParseResult<NaiveDate> = NaiveDate::parse_from_str(date, "%Y-%m-%d");
-    let dt = dt.unwrap_or(default);
-    let dt = Local.from_local_date(&dt).unwrap();
-    let (_, week, _) = dt.isoweekdate();
-    let year = dt.year();
+fn week_of(date: &NaiveDate) -> String {
+    let week = date.iso_week().week();
+    let year = date.year();
     format!("{}, wk {}", year, week)
 }
 
-#[derive(Debug)]
-struct Prediction(u8, u8, u8, u8); // pr-pl / pr-pn : ac-pl / ac-pn
-#[derive(Debug)]
-struct Time(u8, u8); // h, m
-
 #[derive(Debug)]
 pub struct Entry {
-    date: String,
+    date: NaiveDate,
     desc: String,
     url: Option<String>,
     pred: Option<Prediction>,
     time: Option<Time>,
 }
 
-pub fn raw_to_entries(raws: &[RawEntry]) -> Vec<Entry> {
-    let date = RefCell::new("2099-01-01".to_string());
-
-    let new_entry = || Entry {
-        date: date.borrow().clone(),
-        desc: String::new(),
+// Oldest first. Each action collects the timestamp and prediction
+// logged after it. Any logged before the first action of a day go to a
+// "New day" entry.
+pub fn worklog_to_entries(worklog: &Worklog) -> Vec<Entry> {
+    let new_entry = |date, desc: &str| Entry {
+        date,
+        desc: desc.to_string(),
         url: None,
         pred: None,
         time: None,
     };
 
-    let mut next_entry = new_entry();
-
-    let mut entries: Vec<_> = raws.iter().filter_map(|raw| {
-        match *raw {
-            RawEntry::Junk(_) => None,
-            RawEntry::NewDay(ref d) => {
-                *date.borrow_mut() = d.clone();
-                next_entry = new_entry();
-                next_entry.desc = "New day".to_string();
-                None
-            }
-            RawEntry::Action(ref s) => {
-                let entry = mem::replace(&mut next_entry, new_entry());
-                next_entry.desc = s.clone();
-                Some(entry)
-            }
-            RawEntry::Time(h, m) => {
-                next_entry.time = Some(Time(h, m));
-                None
+    let mut entries = vec![];
+
+    for day in &worklog.days {
+        let mut next_entry = new_entry(day.date, "New day");
+
+        for entry in &day.entries {
+            match entry.kind {
+                EntryKind::Action(ref s) => {
+                    let entry = mem::replace(&mut next_entry, new_entry(day.date, &s.text));
+                    entries.push(entry);
+                }
+                EntryKind::Time(t) => {
+                    next_entry.time = Some(t);
+                }
+                EntryKind::Prediction(p) => {
+                    next_entry.pred = Some(p);
+                }
+                EntryKind::Junk(..) |
+                EntryKind::ClockIn(..) | EntryKind::ClockOut(..) |
+                EntryKind::Expense(..) | EntryKind::Duration(..) |
+                EntryKind::Pause(..) | EntryKind::Resume(..) | EntryKind::Break(..) |
+                EntryKind::TimeZone(..) => { }
             }
-            RawEntry::Prediction(pl0, pn0, pl1, pn1) => {
-                next_entry.pred = Some(Prediction(pl0, pn0, pl1, pn1));
-                None
-            }
-            RawEntry::ClockIn(..) | RawEntry::ClockOut(..) => None,
-            RawEntry::Expense(..) => None,
         }
-    }).collect();
 
-    entries.push(next_entry);
+        entries.push(next_entry);
+    }
 
     entries
 }
@@ -0,0 +1,80 @@
+// A CSV timesheet: one row per session, and the expenses in a file of
+// their own, each with a header row. Break minutes get a column of
+// their own when the invoice lists breaks. Rounding per day or per
+// invoice leaves the sessions' hours as clocked, so a last row makes up
+// the difference and the amounts add up to what's due for the time.
+
+use std::io::Write;
+use csv::WriterBuilder;
+
+use errors::*;
+use super::ReportRenderer;
+use time_reporting::{InvoiceReport, RoundingScope};
+
+pub struct CsvRenderer {
+    pub delimiter: u8,
+}
+
+impl ReportRenderer for CsvRenderer {
+    fn render(&self, report: &InvoiceReport, out: &mut dyn Write) -> Result<()> {
+        let mut writer = WriterBuilder::new().delimiter(self.delimiter).from_writer(out);
+        let currency = &report.currency;
+        let rate = format!("rate ({})", currency);
+        let amount = format!("amount ({})", currency);
+        let mut header = vec!["date", "start", "end", "minutes"];
+        if report.show_breaks {
+            header.push("break minutes");
+        }
+        header.extend(&["hours", &rate, &amount, "actions"]);
+        writer.write_record(&header).chain_err(|| "writing CSV")?;
+        for item in &report.items {
+            let mut record = vec![
+                item.date.to_string(),
+                item.start.clone(),
+                item.end.clone(),
+                item.minutes.to_string(),
+            ];
+            if report.show_breaks {
+                record.push(item.break_minutes.to_string());
+            }
+            record.extend(vec![
+                item.hours.to_string(),
+                report.rate.to_string(),
+                format!("{:.2}", item.hours * report.rate),
+                item.actions.join("; "),
+            ]);
+            writer.write_record(&record).chain_err(|| "writing CSV")?;
+        }
+        if report.rounding_policy.per != RoundingScope::Session && report.rounding_adjustment != 0.0 {
+            let adjustment = report.rounding_adjustment;
+            let mut record = vec![String::new(); 4];
+            if report.show_breaks {
+                record.push(String::new());
+            }
+            record.extend(vec![
+                adjustment.to_string(),
+                report.rate.to_string(),
+                format!("{:.2}", adjustment * report.rate),
+                format!("rounding adjustment, {}", report.rounding),
+            ]);
+            writer.write_record(&record).chain_err(|| "writing CSV")?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+pub fn write_expenses_csv(report: &InvoiceReport, delimiter: u8, out: &mut dyn Write) -> Result<()> {
+    let mut writer = WriterBuilder::new().delimiter(delimiter).from_writer(out);
+    writer.write_record(["date", &format!("cost ({})", report.currency), "what"])
+        .chain_err(|| "writing CSV")?;
+    for expense in &report.expenses {
+        writer.write_record(&[
+            expense.date.to_string(),
+            format!("{:.2}", expense.cost),
+            expense.what.trim().to_string(),
+        ]).chain_err(|| "writing CSV")?;
+    }
+    writer.flush()?;
+    Ok(())
+}
@@ -0,0 +1,203 @@
+// Invoices as JSON, for other tools to consume.
+//
+// The schema is versioned by `schema_version`, which is incremented
+// whenever a field is removed or changes meaning. Fields may be added
+// without changing the version.
+//
+//     {
+//       "schema_version": 1,
+//       "invoice_no": "Nervos-2021-001",   // null if none
+//       "self_name": "Common Orbit LLC",
+//       "client": "Decrypted Sapiens",     // null if none
+//       "period": { "start": "2021-02-01", "end": "2021-02-28" },
+//       "issue_date": "2021-03-01",        // null if none
+//       "due_date": "2021-03-15",          // null if none
+//       "rate": 200.0,
+//       "currency": "USD",
+//       "timezone": "America/New_York",    // what times are shown in; null
+//                                          // if as written in the worklog
+//       "sessions": [                      // oldest first, unless
+//                                          // `--order newest`
+//         {
+//           "date": "2021-02-02",
+//           "start": "9:05 AM",            // in "timezone" if converted;
+//           "end": "10:40 AM",             // empty for logged durations
+//           "minutes": 95,                 // as clocked, less breaks
+//           "break_minutes": 0,
+//           "hours": 1.5,                  // as billed when rounding per
+//                                          // session, else as clocked
+//           "actions": [
+//             {
+//               "text": "did [thing](http://x.com) #review",
+//               "links": [ { "text": "thing", "url": "http://x.com" } ],
+//               "tags": [ "review" ]
+//             }
+//           ],
+//           "tags": [ { "tag": "review", "minutes": 60 } ]  // time between
+//                                          // timestamps by the tags of the
+//                                          // actions between them
+//         }
+//       ],
+//       "days": [ { "date": "2021-02-02", "minutes": 95, "hours": 1.5 } ],
+//                                          // hours as billed, which with
+//                                          // rounding per invoice is exact
+//       "expenses": [ { "date": "2021-02-03", "cost": 12.5, "what": "parking" } ],
+//       "total_hours": 1.5,                // as billed
+//       "total_minutes": 95,               // as clocked, less breaks
+//       "total_break_minutes": 0,
+//       "rounding": { "increment": 30, "mode": "nearest", "per": "session", "minimum": 0 },
+//       "rounding_adjustment": -0.0833,    // hours billed less hours clocked
+//       "total_expenses": 12.5,
+//       "amount_due": 312.5
+//     }
+
+use std::io::Write;
+use regex::Regex;
+use serde_json;
+
+use errors::*;
+use super::ReportRenderer;
+use parser::tag_spans;
+use time_reporting::{InvoiceReport, Rounding, TagTime};
+
+pub static SCHEMA_VERSION: u32 = 1;
+
+pub struct JsonRenderer;
+
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    schema_version: u32,
+    invoice_no: &'a Option<String>,
+    self_name: &'a str,
+    client: &'a Option<String>,
+    period: Period,
+    issue_date: Option<String>,
+    due_date: Option<String>,
+    rate: f64,
+    currency: &'a str,
+    timezone: &'a Option<String>,
+    sessions: Vec<JsonSession<'a>>,
+    days: Vec<JsonDay>,
+    expenses: Vec<JsonExpense<'a>>,
+    total_hours: f64,
+    total_minutes: u32,
+    total_break_minutes: u32,
+    rounding: &'a Rounding,
+    rounding_adjustment: f64,
+    total_expenses: f64,
+    amount_due: f64,
+}
+
+#[derive(Serialize)]
+struct Period {
+    start: String,
+    end: String,
+}
+
+#[derive(Serialize)]
+struct JsonSession<'a> {
+    date: String,
+    start: &'a str,
+    end: &'a str,
+    minutes: u32,
+    break_minutes: u32,
+    hours: f64,
+    actions: Vec<JsonAction<'a>>,
+    tags: &'a [TagTime],
+}
+
+#[derive(Serialize)]
+struct JsonDay {
+    date: String,
+    minutes: u32,
+    hours: f64,
+}
+
+#[derive(Serialize)]
+struct JsonAction<'a> {
+    text: &'a str,
+    links: Vec<Link<'a>>,
+    tags: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct Link<'a> {
+    text: &'a str,
+    url: &'a str,
+}
+
+#[derive(Serialize)]
+struct JsonExpense<'a> {
+    date: String,
+    cost: f64,
+    what: &'a str,
+}
+
+impl ReportRenderer for JsonRenderer {
+    fn render(&self, report: &InvoiceReport, out: &mut dyn Write) -> Result<()> {
+        let json = JsonReport {
+            schema_version: SCHEMA_VERSION,
+            invoice_no: &report.invoice_no,
+            self_name: &report.self_name,
+            client: &report.client,
+            period: Period {
+                start: report.start.to_string(),
+                end: report.end.to_string(),
+            },
+            issue_date: report.issue_date.map(|d| d.to_string()),
+            due_date: report.due_date.map(|d| d.to_string()),
+            rate: report.rate,
+            currency: &report.currency,
+            timezone: &report.timezone,
+            sessions: report.items.iter().map(|item| JsonSession {
+                date: item.date.to_string(),
+                start: &item.start,
+                end: &item.end,
+                minutes: item.minutes,
+                break_minutes: item.break_minutes,
+                hours: item.hours,
+                actions: item.actions.iter().map(|a| JsonAction {
+                    text: a,
+                    links: links(a),
+                    tags: tag_spans(a).into_iter().map(|(_, tag)| tag.to_string()).collect(),
+                }).collect(),
+                tags: &item.tags,
+            }).collect(),
+            days: report.days.iter().map(|day| JsonDay {
+                date: day.date.to_string(),
+                minutes: day.minutes,
+                hours: day.hours,
+            }).collect(),
+            expenses: report.expenses.iter().map(|e| JsonExpense {
+                date: e.date.to_string(),
+                cost: e.cost,
+                what: e.what.trim(),
+            }).collect(),
+            total_hours: report.total_hours,
+            total_minutes: report.total_minutes,
+            total_break_minutes: report.total_break_minutes,
+            rounding: &report.rounding_policy,
+            rounding_adjustment: report.rounding_adjustment,
+            total_expenses: report.total_expenses,
+            amount_due: report.amount_due,
+        };
+
+        serde_json::to_writer_pretty(&mut *out, &json).chain_err(|| "writing JSON")?;
+        writeln!(out)?;
+        Ok(())
+    }
+}
+
+// Every markdown `[text](url)` in an action
+fn links<'a>(action: &'a str) -> Vec<Link<'a>> {
+    lazy_static! {
+        static ref REGEX: Regex = Regex::new(r"\[([^\]]*)\]\(([^)]*)\)").unwrap();
+    }
+
+    REGEX.captures_iter(action).map(|caps| {
+        Link {
+            text: caps.get(1).expect("").as_str(),
+            url: caps.get(2).expect("").as_str(),
+        }
+    }).collect()
+}
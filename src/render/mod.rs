@@ -0,0 +1,33 @@
+// Rendering an `InvoiceReport` to some output format.
+
+use std::io::Write;
+use std::path::Path;
+
+use errors::*;
+use time_reporting::{InvoiceOptions, InvoiceReport};
+
+mod csv;
+mod json;
+mod template;
+
+pub use self::csv::{CsvRenderer, write_expenses_csv};
+pub use self::json::{JsonRenderer, SCHEMA_VERSION};
+pub use self::template::TemplateRenderer;
+
+pub trait ReportRenderer {
+    fn render(&self, report: &InvoiceReport, out: &mut dyn Write) -> Result<()>;
+}
+
+// The built-in formats; a templates directory may add more
+pub static FORMATS: &[&str] = &["normal", "ds", "json", "csv"];
+
+pub fn renderer(opts: &InvoiceOptions) -> Result<Box<dyn ReportRenderer>> {
+    match &opts.format[..] {
+        "json" => Ok(Box::new(JsonRenderer)),
+        "csv" => Ok(Box::new(CsvRenderer { delimiter: opts.csv_delimiter })),
+        format => {
+            let templates_dir = opts.templates.as_ref().map(Path::new);
+            Ok(Box::new(TemplateRenderer::new(format, templates_dir)?))
+        }
+    }
+}
@@ -0,0 +1,135 @@
+// Invoices rendered from minijinja templates.
+//
+// A template named `<format>.html` is looked for in the user's template
+// directory first, then among the built-in ones, so the normal and ds
+// layouts can be overridden as well as added to. Templates see the
+// fields of `InvoiceReport`, and may extend the built-in "base.html".
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use minijinja::{AutoEscape, Environment, Error, ErrorKind};
+use regex::Regex;
+
+use errors::*;
+use super::ReportRenderer;
+use time_reporting::{hm, InvoiceReport};
+
+static BUILTIN_TEMPLATES: &[(&str, &str)] = &[
+    ("base.html", include_str!("templates/base.html")),
+    ("normal.html", include_str!("templates/normal.html")),
+    ("ds.html", include_str!("templates/ds.html")),
+];
+
+pub struct TemplateRenderer {
+    env: Environment<'static>,
+    name: String,
+}
+
+impl TemplateRenderer {
+    // Fails if there is no template for `format`
+    pub fn new(format: &str, templates_dir: Option<&Path>) -> Result<TemplateRenderer> {
+        let dir = templates_dir.map(Path::to_path_buf);
+        let mut env = Environment::new();
+        env.set_loader(move |name| load_template(dir.as_deref(), name));
+        // Whitespace is kept as written apart from the lines holding
+        // block tags, and the output is not escaped, so actions can
+        // carry their own markup
+        env.set_trim_blocks(true);
+        env.set_lstrip_blocks(true);
+        env.set_keep_trailing_newline(true);
+        env.set_auto_escape_callback(|_| AutoEscape::None);
+        env.add_filter("fixed", fixed);
+        env.add_filter("signed", signed);
+        env.add_filter("num", num);
+        env.add_filter("hours", hours);
+        env.add_filter("hm", hm);
+        env.add_filter("md_link", md_link);
+        env.add_filter("multiline", multiline);
+
+        let name = format!("{}.html", format);
+        if let Err(e) = env.get_template(&name) {
+            if e.kind() == ErrorKind::TemplateNotFound {
+                bail!("unknown output format '{}', expected one of {} or a template in the templates directory",
+                      format, super::FORMATS.join(", "));
+            }
+            return Err(e).chain_err(|| format!("loading template {}", name));
+        }
+
+        Ok(TemplateRenderer { env, name })
+    }
+}
+
+impl ReportRenderer for TemplateRenderer {
+    fn render(&self, report: &InvoiceReport, out: &mut dyn Write) -> Result<()> {
+        let template = self.env.get_template(&self.name)
+            .chain_err(|| format!("loading template {}", self.name))?;
+        template.render_captured_to(report, out)
+            .chain_err(|| format!("rendering template {}", self.name))?;
+        Ok(())
+    }
+}
+
+fn load_template(dir: Option<&Path>, name: &str) -> ::std::result::Result<Option<String>, Error> {
+    if let Some(dir) = dir {
+        let path: PathBuf = dir.join(name);
+        match fs::read_to_string(&path) {
+            Ok(text) => return Ok(Some(text)),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => { }
+            Err(e) => {
+                return Err(Error::new(ErrorKind::InvalidOperation,
+                                      format!("reading {}", path.display())).with_source(e));
+            }
+        }
+    }
+    Ok(BUILTIN_TEMPLATES.iter().find(|t| t.0 == name).map(|t| t.1.to_string()))
+}
+
+// `{{ hours|fixed(1) }}`, like Rust's `{:.1}`
+fn fixed(value: f64, digits: usize) -> String {
+    format!("{:.*}", digits, value)
+}
+
+// `{{ adjustment|signed(2) }}`, like Rust's `{:+.2}`
+fn signed(value: f64, digits: usize) -> String {
+    format!("{:+.*}", digits, value)
+}
+
+// Hours to one decimal place, or two if that isn't exact, so quarter
+// hours aren't rounded again on the invoice
+fn hours(value: f64) -> String {
+    let s = format!("{:.2}", value);
+    match s.strip_suffix('0') {
+        Some(s) => s.to_string(),
+        None => s,
+    }
+}
+
+// A number as Rust prints it, so 200.0 is "200"
+fn num(value: f64) -> String {
+    value.to_string()
+}
+
+// A markdown `[text](link)` in an action becomes an HTML link
+fn md_link(text: &str) -> String {
+    lazy_static! {
+        static ref REGEX: Regex = Regex::new(r"^(.*)\[(.*)\]\((.*)\)(.*)$").unwrap();
+    }
+
+    let caps = REGEX.captures(text);
+    if let Some(caps) = caps {
+        let pre = &caps[1];
+        let text = &caps[2];
+        let link = &caps[3];
+        let post = &caps[4];
+        format!("{}<a href='{}'>{}</a>{}",
+                pre, link, text, post)
+    } else {
+        text.to_string()
+    }
+}
+
+// Addresses and the like, one line per line
+fn multiline(text: &str) -> String {
+    text.trim().lines().collect::<Vec<_>>().join("<br>")
+}
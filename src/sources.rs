@@ -0,0 +1,217 @@
+// The files a worklog is read from.
+//
+// A worklog may be split across files, say one per year with the
+// expenses kept in another. They are given as files, directories of
+// `.md` files or globs like `worklog-*.md`, and a worklog can pull in
+// more with an `include: path` line, the path being relative to it.
+// Entries are numbered by their line in all the files strung together
+// in the order they were read, so the rest of the crate can treat them
+// as one text; `Sources` maps those numbers back to a file and line for
+// diagnostics.
+
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use diagnostics::{Diagnostics, LineDiagnostics};
+use parser::include_path;
+use errors::*;
+
+#[derive(Debug, Clone, Default)]
+pub struct Sources {
+    files: Vec<SourceFile>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    pub path: String,
+    pub text: String,
+    // Lines in the files before this one
+    offset: usize,
+    len: usize,
+}
+
+impl Sources {
+    pub fn new() -> Sources {
+        Sources::default()
+    }
+
+    // Read files, directories and globs, and everything they include
+    pub fn load(paths: &[String]) -> Result<Sources> {
+        let mut sources = Sources::new();
+        let mut seen = HashSet::new();
+        for path in paths {
+            for file in expand(Path::new(path))? {
+                sources.load_file(&file, &mut seen)?;
+            }
+        }
+        if sources.files.is_empty() {
+            bail!("no worklog files in {}", paths.join(", "));
+        }
+        Ok(sources)
+    }
+
+    // A file included more than once is only read the first time
+    fn load_file(&mut self, path: &Path, seen: &mut HashSet<PathBuf>) -> Result<()> {
+        let canonical = fs::canonicalize(path).chain_err(|| format!("reading {}", path.display()))?;
+        if !seen.insert(canonical) {
+            return Ok(());
+        }
+
+        let mut text = String::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut text))
+            .chain_err(|| format!("reading {}", path.display()))?;
+        let includes: Vec<(usize, String)> = text.lines().enumerate()
+            .filter_map(|(i, line)| include_path(line).map(|p| (i + 1, p.to_string())))
+            .collect();
+        self.add(&path.to_string_lossy(), text);
+
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        for (line, include) in includes {
+            let context = || format!("included from {}:{}", path.display(), line);
+            for file in expand(&dir.join(&include)).chain_err(context)? {
+                self.load_file(&file, seen).chain_err(context)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn add(&mut self, path: &str, text: String) {
+        let offset = self.files.last().map_or(0, |f| f.offset + f.len);
+        let len = text.lines().count();
+        self.files.push(SourceFile { path: path.to_string(), text, offset, len });
+    }
+
+    pub fn files(&self) -> &[SourceFile] {
+        &self.files
+    }
+
+    // Every line of every file, so that line `n` is `lines()[n - 1]`
+    pub fn lines(&self) -> Vec<&str> {
+        self.files.iter().flat_map(|f| f.text.lines()).collect()
+    }
+
+    // The file a line is in, and its line within the file
+    pub fn locate(&self, line: usize) -> (&SourceFile, usize) {
+        let file = self.files.iter().rev().find(|f| f.offset < line).unwrap_or(&self.files[0]);
+        (file, line - file.offset)
+    }
+
+    // The line number of a line in one of the files
+    pub fn line_number(&self, path: &str, line: usize) -> Option<usize> {
+        self.files.iter().find(|f| f.path == path).map(|f| f.offset + line)
+    }
+
+    // Start reporting problems against a line
+    pub fn diag<'a>(&'a self, diags: &'a mut Diagnostics, line: usize) -> LineDiagnostics<'a> {
+        let (file, local) = self.locate(line);
+        let source = file.text.lines().nth(local - 1).unwrap_or("");
+        diags.line(&file.path, local, source)
+    }
+
+    // "worklog.md:12", for messages
+    pub fn describe(&self, line: usize) -> String {
+        let (file, local) = self.locate(line);
+        format!("{}:{}", file.path, local)
+    }
+
+    // "line 12" in a message about line `from`, or "worklog.md:12" if
+    // that is in another file
+    pub fn line_ref(&self, line: usize, from: usize) -> String {
+        let (file, local) = self.locate(line);
+        if file.path == self.locate(from).0.path {
+            format!("line {}", local)
+        } else {
+            self.describe(line)
+        }
+    }
+}
+
+impl SourceFile {
+    // Line `n` of this file, numbered among all the files
+    pub fn line_number(&self, n: usize) -> usize {
+        self.offset + n
+    }
+}
+
+// Replace a file's contents, writing a temporary file and renaming it
+// over so that a crash leaves either the old file or the new one
+pub fn write_atomic(path: &Path, text: &str) -> Result<()> {
+    let mut name = path.file_name().map(|n| n.to_os_string()).unwrap_or_default();
+    name.push(".tmp");
+    let tmp = path.with_file_name(name);
+    File::create(&tmp)
+        .and_then(|mut f| f.write_all(text.as_bytes()).and_then(|_| f.sync_all()))
+        .chain_err(|| format!("writing {}", tmp.display()))?;
+    fs::rename(&tmp, path).chain_err(|| format!("writing {}", path.display()))?;
+    Ok(())
+}
+
+// Where the config and ledger for a worklog given as `path` go: the
+// directory it is in, or the directory itself if given one
+pub fn directory(path: &Path) -> &Path {
+    if path.is_dir() {
+        path
+    } else {
+        path.parent().unwrap_or_else(|| Path::new(""))
+    }
+}
+
+// The `.md` files in a directory, the files matching a glob in its last
+// component, or just the file
+fn expand(path: &Path) -> Result<Vec<PathBuf>> {
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+
+    // The directory to look in, and the glob to match if not `*.md`
+    let (dir, glob) = if path.is_dir() {
+        (path, None)
+    } else if name.contains('*') || name.contains('?') {
+        match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => (dir, Some(name)),
+            _ => (Path::new("."), Some(name)),
+        }
+    } else {
+        return Ok(vec![path.to_path_buf()]);
+    };
+
+    let mut files = vec![];
+    for entry in fs::read_dir(dir).chain_err(|| format!("reading {}", dir.display()))? {
+        let file = entry.chain_err(|| format!("reading {}", dir.display()))?.path();
+        let matches = match glob {
+            Some(ref glob) => file.file_name().is_some_and(|n| wildcard_match(glob, &n.to_string_lossy())),
+            None => file.extension().is_some_and(|e| e == "md"),
+        };
+        if file.is_file() && matches {
+            // "worklog.md" rather than "./worklog.md"
+            files.push(file.strip_prefix(".").map(Path::to_path_buf).unwrap_or(file));
+        }
+    }
+    if files.is_empty() {
+        bail!("no worklog files match {}", path.display());
+    }
+    files.sort();
+    Ok(files)
+}
+
+// `*` matches any run of characters and `?` any one
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    // matched[j]: the pattern so far matches the first j chars of name
+    let mut matched = vec![false; name.len() + 1];
+    matched[0] = true;
+    for &p in &pattern {
+        let mut next = vec![false; name.len() + 1];
+        for j in 0 ..= name.len() {
+            next[j] = match p {
+                '*' => matched[j] || (j > 0 && next[j - 1]),
+                '?' => j > 0 && matched[j - 1],
+                c => j > 0 && matched[j - 1] && name[j - 1] == c,
+            };
+        }
+        matched = next;
+    }
+    matched[name.len()]
+}
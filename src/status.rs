@@ -0,0 +1,119 @@
+// Where things stand now, for the `status` command: the sessions
+// clocked in and not yet out, and the time worked today and this week
+// for each company, counting open sessions up to now.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Timelike};
+
+use model::{elapsed, OpenSession, Time, Worklog};
+use time_reporting::{self as tr, hm, BillingPolicy, Midnight, Minutes};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Status {
+    pub now: NaiveDateTime,
+    pub open: Vec<OpenSession>,
+    // Minutes by company, including open sessions so far
+    pub today: BTreeMap<Option<String>, Minutes>,
+    pub week: BTreeMap<Option<String>, Minutes>,
+}
+
+// Weeks start on Monday
+pub fn status(worklog: &Worklog, now: NaiveDateTime) -> Status {
+    let today = now.date();
+    let monday = today - Duration::days(today.weekday().num_days_from_monday() as i64);
+    let open = worklog.open_sessions.clone();
+
+    let mut companies: BTreeSet<Option<String>> = worklog.sessions().map(|s| s.company.clone()).collect();
+    companies.extend(worklog.days.iter().flat_map(|d| d.durations()).map(|(_, d)| d.company.clone()));
+
+    // Unrounded, with sessions past midnight counted on each day
+    let policy = BillingPolicy { midnight: Midnight::Split, ..BillingPolicy::default() };
+    let worked = |from: NaiveDate| -> BTreeMap<Option<String>, Minutes> {
+        let mut minutes: BTreeMap<Option<String>, Minutes> = companies.iter().map(|company| {
+            (company.clone(), tr::bill(worklog, company, from, today, &policy).minutes())
+        }).collect();
+        for session in &open {
+            *minutes.entry(session.company.clone()).or_insert(0) += worked_since(session, from, now);
+        }
+        minutes.retain(|_, &mut m| m > 0);
+        minutes
+    };
+
+    Status { now, today: worked(today), week: worked(monday), open }
+}
+
+// Minutes worked in an open session until `now`, less breaks and any
+// pause still going, counting only from the start of `from`
+fn worked_since(session: &OpenSession, from: NaiveDate, now: NaiveDateTime) -> Minutes {
+    let midnight = Time::new(0, 0);
+    let clamp = |(date, time): (NaiveDate, Time)| if date < from { (from, midnight) } else { (date, time) };
+    let start = match session.start {
+        Some(start) => clamp((session.date, start)),
+        None => return 0,
+    };
+    let now = (now.date(), Time::new(now.hour() as u8, now.minute() as u8));
+    let span = |from: (NaiveDate, Time), to: (NaiveDate, Time)| elapsed((from.0, from.1, None), (to.0, to.1, None));
+
+    let breaks: Minutes = session.breaks.iter().filter(|b| b.date >= from).map(|b| b.minutes).sum();
+    let paused = session.paused.map_or(0, |since| span(clamp(since), now));
+    span(start, now).saturating_sub(breaks + paused)
+}
+
+impl Status {
+    // For a shell prompt, e.g. "Nervos 2:15 | today 5:30 | week 14:45"
+    pub fn one_line(&self) -> String {
+        let open = if self.open.is_empty() {
+            "out".to_string()
+        } else {
+            self.open.iter().map(|s| {
+                let name = s.company.as_deref().unwrap_or("in");
+                match s.start {
+                    Some(_) => {
+                        let paused = if s.paused.is_some() { " paused" } else { "" };
+                        format!("{} {}{}", name, hm(worked_since(s, s.date, self.now)), paused)
+                    }
+                    None => name.to_string(),
+                }
+            }).collect::<Vec<_>>().join(", ")
+        };
+        format!("{} | today {} | week {}", open, hm(self.today.values().sum()), hm(self.week.values().sum()))
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.open.is_empty() {
+            writeln!(f, "not clocked in")?;
+        }
+        for session in &self.open {
+            let name = session.company.as_deref().unwrap_or("no company");
+            match session.start {
+                Some(start) => {
+                    let on = if session.date == self.now.date() { String::new() } else { format!("{} ", session.date) };
+                    let paused = match session.paused {
+                        Some((_, since)) => format!(", paused since {}", since),
+                        None => String::new(),
+                    };
+                    writeln!(f, "clocked in for {} since {}{}, {} worked{}", name, on, start,
+                             hm(worked_since(session, session.date, self.now)), paused)?;
+                }
+                None => writeln!(f, "clocked in for {} on {}, with no timestamp", name, session.date)?,
+            }
+        }
+        writeln!(f, "today: {}", totals(&self.today))?;
+        write!(f, "this week: {}", totals(&self.week))
+    }
+}
+
+// "5:30 (Nervos 4:30, Other 1:00)"
+fn totals(minutes: &BTreeMap<Option<String>, Minutes>) -> String {
+    let total = hm(minutes.values().sum());
+    if minutes.len() < 2 && minutes.keys().all(|c| c.is_none()) {
+        return total;
+    }
+    let each: Vec<String> = minutes.iter().map(|(company, &m)| {
+        format!("{} {}", company.as_deref().unwrap_or("no company"), hm(m))
+    }).collect();
+    format!("{} ({})", total, each.join(", "))
+}
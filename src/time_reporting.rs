@@ -1,398 +1,701 @@
 // run with
 //
-//     cargo run -- ~/brson.github.com/worklog.md tr 2021-02-01 2021-02-28 200 "Common Orbit LLC" Nervos "Decrypted Sapiens" 1 2021-03-28 2021-04-15 > outputfile.md
+//     cargo run -- -f ~/brson.github.com/worklog.md invoice --from 2021-02-01 --to 2021-02-28 \
+//         --rate 200 --name "Common Orbit LLC" --project Nervos --client "Decrypted Sapiens" \
+//         --invoice-no 1 --issued 2021-03-28 --due 2021-04-15 > outputfile.md
 //
-// print ds-style output by setting OUTPUT=DS
+// or set up a client profile in the config file and run
+//
+//     cargo run -- invoice --client Nervos --month 2021-02 > outputfile.md
+//
+// print ds-style output with `--format ds`, or `style = "ds"` in the
+// client profile, JSON for other tools with `--format json`, and a CSV
+// timesheet with `--format csv`, with `--expenses-output expenses.csv`
+// for the expenses. `--tag review` bills only the time clocked between
+// timestamps around actions tagged `#review`, and durations and
+// expenses tagged `#review`.
+// Invoices are rendered from templates in `src/render/templates`, which
+// can be overridden or added to from a directory given with
+// `--templates`, or `templates` in the config file.
 
 
 use chrono::*;
+use chrono_tz::Tz;
 use errors::*;
-use std::mem;
-use std::fmt::Display;
-use lazy_static::lazy_static;
-use regex::Regex;
-use std::env;
-
-use RawEntry;
-
-type MinuteOfDay = u32;
-type Minutes = u32;
-type Action = String;
-type Hours = f64;
-
-struct Expense {
-    date: NaiveDate,
-    cost: f64,
-    what: String,
-}
-
-pub fn do_time_report(entries: &[RawEntry], start: NaiveDate, end: NaiveDate, rate: f64,
-                      self_name: String, project: Option<String>, client: Option<String>,
-                      invoice_no: Option<u32>, issue_date: Option<NaiveDate>, due_date: Option<NaiveDate>) -> Result<()> {
-    // Split entries by date, while recording dates of each subslice
-    let mut dates = vec![String::new()];
-    let mut entry_days: Vec<_> = entries.split(|e| {
-        match *e {
-            RawEntry::NewDay(ref s) => {
-                dates.push(s.to_string());
-                true
-            }
-            _ => false
-        }
-    }).collect();
-
-    assert_eq!(dates.len(), entry_days.len());
-
-    // The first entry is junk
-    dates.remove(0);
-    entry_days.remove(0);
-
-    // Convert dates to NaiveDate
-    let dates: Result<Vec<_>> = dates.iter().map(|s| {
-        NaiveDate::parse_from_str(s, "%Y-%m-%d").map_err(|e| Error::from(e.to_string()))
-    }).collect();
-    let dates = dates?;
-
-    let dated_entries: Vec<(NaiveDate, &[RawEntry])> = dates.into_iter().zip(entry_days.into_iter()).collect();
-
-    // Filter dates that are out of range
-    let dated_entries: Vec<_> = dated_entries.into_iter().filter(|&(date, _)| {
-        date >= start && date <= end
-    }).collect();
-
-    // Worklog goes from newest dates to oldest. For reporting that
-    // needs to be reversed.
-    let mut dated_entries = dated_entries;
-    dated_entries.reverse();
-
-    // Dates containing clocked-in actions
-    let mut dated_timeslices: Vec<(NaiveDate, Minutes, Vec<Action>)> = vec![];
-
-    let timestamp_to_minute_of_day = |h, m| (h as u32) * 60 + (m as u32);
-
-    let mut expenses = vec![];
-    
-    for (date, entries) in dated_entries.into_iter() {
-        let mut clock_in: Option<MinuteOfDay> = None;
-        let mut actions: Vec<String> = vec![];
-        for (i, entry) in entries.iter().enumerate() {
-            match *entry {
-                RawEntry::ClockIn(ref c) if *c == project => {
-                    if clock_in.is_some() {
-                        bail!("clock-in without clock-out on {:?}", date);
-                    }
-                    match entries.get(i + 1) {
-                        Some(&RawEntry::Time(h, m)) => {
-                            clock_in = Some(timestamp_to_minute_of_day(h, m));
-                        }
-                        _ => bail!("clock-in not followed by timestamp on {:?}", date)
-                    }
-                }
-                RawEntry::ClockOut(ref c) if *c == project => {
-                    match clock_in {
-                        Some(clock_in_) => {
-                            match entries.get(i - 1) {
-                                Some(&RawEntry::Time(h, m)) => {
-                                    let clock_out = timestamp_to_minute_of_day(h, m);
-                                    if clock_out <= clock_in_ {
-                                        bail!("clock-out less than clock-in on {:?}", date);
-                                    }
-
-                                    // Push actions onto dated timeslices
-                                    let minutes = clock_out - clock_in_;
-                                    let new_actions = mem::replace(&mut actions, vec![]);
-                                    dated_timeslices.push((date, minutes, new_actions));
-                                    clock_in = None;
-                                }
-                                _ => bail!("clock-out not preceded by timestamp on {:?}", date)
-                            }
-                        }
-                        _ => bail!("clock-out without clock-in on {:?}", date)
-                    }
-                }
-                RawEntry::ClockIn(..) | RawEntry::ClockOut(..) => { }
-                RawEntry::Action(ref s) => {
-                    if clock_in.is_some() {
-                        actions.push(s.to_string());
-                    }
-                }
-                RawEntry::Expense(cost, ref what) => {
-                    expenses.push(Expense { date, cost, what: what.to_string() });
-                }
-                RawEntry::NewDay(..) => unreachable!(),
-                RawEntry::Junk(..) |
-                RawEntry::Time(..) |
-                RawEntry::Prediction(..) => { }
-            }
-        }
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+
+use config::Identity;
+use model::{DayOrder, Worklog, EntryKind, Session, Tag, Time, MINUTES_PER_DAY};
+use parser::tag_spans;
+use render;
+
+pub type Minutes = u32;
+pub type Action = String;
+pub type Hours = f64;
+
+// Minutes as hours and minutes, e.g. "3:05"
+pub fn hm(minutes: Minutes) -> String {
+    format!("{}:{:02}", minutes / 60, minutes % 60)
+}
+
+// How clocked time is rounded for billing. The default, rounding each
+// session to the nearest half hour, is what every invoice used before
+// this was configurable.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Rounding {
+    // In minutes; 0 bills exactly what was clocked
+    pub increment: u32,
+    pub mode: RoundingMode,
+    pub per: RoundingScope,
+    // Least billed for any session, day or invoice with time clocked,
+    // in minutes
+    pub minimum: u32,
+}
 
-        if clock_in.is_some() {
-            bail!("clock-in without clock-out on {:?}", date);
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RoundingMode {
+    Nearest,
+    Up,
+    Down,
+}
+
+// Which expenses an invoice bills. Expenses don't name a company, so
+// normally all of them in the period are billed; when several clients
+// are invoiced at once, each bills only those logged during its own
+// sessions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExpenseScope {
+    #[default]
+    All,
+    Sessions,
+}
+
+// How the work in a worklog is billed
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BillingPolicy {
+    pub rounding: Rounding,
+    pub expense_scope: ExpenseScope,
+    pub midnight: Midnight,
+    // The client's time zone, which the invoice shows times and dates
+    // in. Only sessions clocked under a `tz:` line can be converted.
+    pub timezone: Option<Tz>,
+    pub tags: TagDisplay,
+    // Only sessions, durations and expenses with this tag are billed
+    pub tag: Option<Tag>,
+}
+
+// Whether `#tag` and `key:value` tags are left in the actions on an
+// invoice
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TagDisplay {
+    #[default]
+    Keep,
+    Strip,
+}
+
+// Which day a session running past midnight is billed on: the day it
+// started, or each day for the part worked on it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Midnight {
+    #[default]
+    Start,
+    Split,
+}
+
+// What is rounded: each session, each day's total, or the whole invoice
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RoundingScope {
+    Session,
+    Day,
+    Invoice,
+}
+
+// Everything about an invoice other than the work being billed
+#[derive(Debug, Clone)]
+pub struct InvoiceOptions {
+    pub start: NaiveDate,
+    pub end: NaiveDate,
+    pub rate: f64,
+    pub currency: String,
+    // One of `render::FORMATS`, or a template in `templates`
+    pub format: String,
+    // Directory of user templates
+    pub templates: Option<String>,
+    // Field delimiter for CSV output
+    pub csv_delimiter: u8,
+    pub policy: BillingPolicy,
+    pub self_name: String,
+    pub identity: Identity,
+    // The company named in clock-in lines
+    pub project: Option<String>,
+    // Who is billed
+    pub client: Option<String>,
+    pub client_address: Option<String>,
+    pub invoice_no: Option<String>,
+    pub issue_date: Option<NaiveDate>,
+    pub due_date: Option<NaiveDate>,
+    pub notes: Option<String>,
+    // List the breaks taken within sessions
+    pub show_breaks: bool,
+    // Which way days are listed
+    pub order: DayOrder,
+}
+
+// The work billed to a project over a period
+#[derive(Debug, Clone)]
+pub struct Billed {
+    // Only days with sessions or expenses, oldest first
+    pub days: Vec<BilledDay>,
+    // The worklog lines the billed sessions and expenses came from
+    pub lines: Vec<usize>,
+    pub rounding: Rounding,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BilledDay {
+    #[serde(with = "::ledger::date_format")]
+    pub date: NaiveDate,
+    #[serde(default)]
+    pub sessions: Vec<BilledSession>,
+    #[serde(default)]
+    pub expenses: Vec<BilledExpense>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BilledSession {
+    // As written in the worklog, e.g. "9:05 AM"; empty for a logged
+    // duration
+    pub start: String,
+    pub end: String,
+    // As clocked, less breaks
+    pub minutes: Minutes,
+    #[serde(default)]
+    pub break_minutes: Minutes,
+    // Rounded if rounding is per session, otherwise exact
+    pub hours: Hours,
+    pub actions: Vec<Action>,
+    // Minutes between timestamps by the tags of the actions between
+    // them, e.g. "review" or "issue:1234"
+    #[serde(default)]
+    pub tags: BTreeMap<String, Minutes>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BilledExpense {
+    pub cost: f64,
+    pub what: String,
+}
+
+impl Billed {
+    // Billed hours, after rounding
+    pub fn hours(&self) -> Hours {
+        match self.rounding.per {
+            RoundingScope::Session | RoundingScope::Day => {
+                self.days.iter().map(|d| self.day_hours(d)).sum()
+            }
+            RoundingScope::Invoice => self.rounding.round(self.minutes()),
         }
     }
 
-    // Round timeslices to half-hours
-
-    let dated_timeslices: Vec<(NaiveDate, Hours, Vec<Action>)> =
-        dated_timeslices.into_iter().map(|(date, minutes, actions)| {
-            let minutes = minutes as f64;
-            let hours = minutes / 60.0;
-            // For Reddit at least I need reports accurate to the half-hour
-            let halfhours = hours * 2.0;
-            let rounded_halfhours = halfhours.round();
-            let hours = rounded_halfhours / 2.0;
-            (date, hours, actions)
-        }).collect();
-
-    print_report(start, end, rate, &dated_timeslices, expenses, self_name, client, invoice_no, issue_date, due_date)
-}
-
-fn print_report(start: NaiveDate, end: NaiveDate, rate: f64,
-                data: &[(NaiveDate, Hours, Vec<Action>)],
-                expenses: Vec<Expense>,
-                self_name: String,
-                client: Option<String>,
-                invoice_no: Option<u32>,
-                issue_date: Option<NaiveDate>, due_date: Option<NaiveDate>) -> Result<()> {
-    let style = env::var("OUTPUT").unwrap_or("normal".to_string());
-    if style == "ds" {
-        print_report_ds(start, end, rate, data, expenses, self_name, client, invoice_no, issue_date, due_date)
-    } else {
-        print_report_normal(start, end, rate, data, expenses, self_name, client, invoice_no, issue_date, due_date)
+    // Clocked minutes, before rounding
+    pub fn minutes(&self) -> Minutes {
+        self.days.iter().map(|d| d.minutes()).sum()
     }
-}
-
-fn print_report_normal(start: NaiveDate, end: NaiveDate, rate: f64,
-                       data: &[(NaiveDate, Hours, Vec<Action>)],
-                       expenses: Vec<Expense>,
-                       self_name: String,
-                       client: Option<String>,
-                       invoice_no: Option<u32>,
-                       issue_date: Option<NaiveDate>, due_date: Option<NaiveDate>) -> Result<()> {
 
-    fn print_table_row_2(v1: impl Display, v2: impl Display) {
-        println!("<tr><td>{}</td><td>{}</td></tr>", v1, v2);
+    // Billed hours for one day. With rounding per invoice this is exact.
+    pub fn day_hours(&self, day: &BilledDay) -> Hours {
+        match self.rounding.per {
+            RoundingScope::Session => day.sessions.iter().map(|s| s.hours).sum(),
+            RoundingScope::Day => self.rounding.round(day.minutes()),
+            RoundingScope::Invoice => day.minutes() as f64 / 60.0,
+        }
     }
 
-    let total_hours = data.iter().fold(0.0, |sum, &(_, hours, _)| sum + hours);
-    let total_expenses = expenses.iter().fold(0.0, |total, expense| total + expense.cost);
-    let amount_due = rate * total_hours + total_expenses;
-
-    println!("<!doctype html>");
-    println!("<meta charset='utf-8'>");
-    println!("{}", STYLE);
-    println!("");
-    println!("<h1>Invoice from {}</h1>", self_name);
-    println!();
-    println!("<table>");
-    print_table_row_2("name:", self_name);
-    print_table_row_2("email:", "andersrb@gmail.com");
-    if let Some(client) = client {
-        print_table_row_2("client:", client);
+    // Billed hours less clocked hours
+    pub fn adjustment(&self) -> Hours {
+        self.hours() - self.minutes() as f64 / 60.0
     }
-    if let Some(invoice_no) = invoice_no {
-        print_table_row_2("invoice number:", invoice_no);
+
+    pub fn expenses(&self) -> f64 {
+        self.days.iter().flat_map(|d| d.expenses.iter()).fold(0.0, |sum, e| sum + e.cost)
     }
-    print_table_row_2("reporting period:", format!("{} - {}", start, end));
-    if let Some(issue_date) = issue_date {
-        print_table_row_2("issue date:", issue_date);
+
+    pub fn amount_due(&self, rate: f64) -> f64 {
+        rate * self.hours() + self.expenses()
     }
-    if let Some(due_date) = due_date {
-        print_table_row_2("due date:", due_date);
+}
+
+impl BilledDay {
+    pub fn minutes(&self) -> Minutes {
+        self.sessions.iter().map(|s| s.minutes).sum()
     }
-    print_table_row_2("total hours:", format!("{:.1}", total_hours));
-    print_table_row_2("hourly rate:", format!("{:} USD", rate));
-    if total_expenses > 0.0 {
-        print_table_row_2("expenses:", format!("{:.2} USD", total_expenses));
+}
+
+// Collect the sessions clocked to `project`, and the expenses the
+// policy says to, from `start` through `end`
+pub fn bill(worklog: &Worklog, project: &Option<String>, start: NaiveDate, end: NaiveDate,
+            policy: &BillingPolicy) -> Billed {
+    let rounding = &policy.rounding;
+    let mut billed_days: BTreeMap<NaiveDate, BilledDay> = BTreeMap::new();
+    let mut lines = vec![];
+
+    let new_day = |date| BilledDay { date, sessions: vec![], expenses: vec![] };
+
+    let tagged = |text: &str| {
+        policy.tag.as_ref().is_none_or(|filter| tag_spans(text).iter().any(|(_, t)| t.matches(filter)))
+    };
+
+    // A session split at midnight may have started before the period
+    for session in worklog.sessions().filter(|s| s.company == *project) {
+        if let Some(ref filter) = policy.tag {
+            if session.tagged_with(filter).next().is_none() {
+                continue;
+            }
+        }
+        let clock = wall_clock(session, policy.timezone);
+        let pieces = match policy.midnight {
+            Midnight::Start => vec![(clock.0, clock.1, clock.3, session.elapsed_minutes())],
+            Midnight::Split => split_at_midnight(clock, session.elapsed_minutes()),
+        };
+        // Breaks come off the part of the session they were taken in, or
+        // the last part if the day they were on isn't one after
+        // converting time zones
+        let mut breaks = vec![0; pieces.len()];
+        for b in &session.breaks {
+            let i = pieces.iter().position(|p| p.0 == b.date).unwrap_or(pieces.len().saturating_sub(1));
+            if let Some(minutes) = breaks.get_mut(i) {
+                *minutes += b.minutes;
+            }
+        }
+        // With a tag, only the time logged with it is billed, on the
+        // part of the session its later timestamp falls in
+        let tagged = policy.tag.as_ref().map(|filter| {
+            let mut minutes = vec![0; pieces.len()];
+            for time in session.tagged_with(filter) {
+                let i = pieces.iter().position(|p| p.0 == time.date).unwrap_or(pieces.len().saturating_sub(1));
+                if let Some(m) = minutes.get_mut(i) {
+                    *m += time.minutes;
+                }
+            }
+            minutes
+        });
+        let mut billed_any = false;
+        for (i, (date, from, to, elapsed)) in pieces.into_iter().enumerate() {
+            if date < start || date > end {
+                continue;
+            }
+            let minutes = match tagged {
+                Some(ref tagged) if tagged[i] == 0 => continue,
+                Some(ref tagged) => tagged[i],
+                None => elapsed.saturating_sub(breaks[i]),
+            };
+            billed_days.entry(date).or_insert_with(|| new_day(date)).sessions.push(BilledSession {
+                start: from.to_string(),
+                end: to.to_string(),
+                minutes,
+                break_minutes: breaks[i],
+                hours: match rounding.per {
+                    RoundingScope::Session => rounding.round(minutes),
+                    _ => minutes as f64 / 60.0,
+                },
+                // Listed once, with the first part of the session
+                actions: if i == 0 {
+                    session.actions.iter().map(|a| action_text(&a.text, policy.tags)).collect()
+                } else {
+                    vec![]
+                },
+                tags: if i == 0 {
+                    session.tag_minutes().iter().map(|(tag, &minutes)| (tag.to_string(), minutes)).collect()
+                } else {
+                    BTreeMap::new()
+                },
+            });
+            billed_any = true;
+        }
+        if billed_any {
+            lines.extend(worklog.session_lines(session));
+        }
     }
-    print_table_row_2("amount due:", format!("{:.2} USD", amount_due));
-    println!("</table>");
-    println!();
-
-    println!("<h2>Summary</h2>");
-    println!();
-    println!("<p>");
-    println!("TODO fill-me-in");
-    println!("</p>");
-    println!();
-
-    println!("<h2>Details</h2>");
-    println!();
-    println!("<table>");
-    println!("<tr><th>Date</th><th>Hours</th><th>Detail</th></tr>");
-
-    for &(date, hours, ref actions) in data {
-        println!("<tr>");
-        println!("<td>{}</td><td>{:2.1}</td>", date, hours);
-        println!("<td>");
-        for action in actions {
-            let action = parse_md_link(action);
-            println!("<p>{}</p>", action);
+
+    for day in worklog.days_between(start, end) {
+        for (line, duration) in day.durations().filter(|&(_, d)| d.company == *project && tagged(&d.what)) {
+            billed_days.entry(day.date).or_insert_with(|| new_day(day.date)).sessions.push(BilledSession {
+                start: String::new(),
+                end: String::new(),
+                minutes: duration.minutes,
+                break_minutes: 0,
+                hours: match rounding.per {
+                    RoundingScope::Session => rounding.round(duration.minutes),
+                    _ => duration.minutes as f64 / 60.0,
+                },
+                actions: if duration.what.is_empty() {
+                    vec![]
+                } else {
+                    vec![action_text(&duration.what, policy.tags)]
+                },
+                tags: BTreeMap::new(),
+            });
+            lines.push(line);
+        }
+
+        for entry in &day.entries {
+            if let EntryKind::Expense(ref expense) = entry.kind {
+                if !tagged(&expense.what) {
+                    continue;
+                }
+                if policy.expense_scope == ExpenseScope::Sessions &&
+                    !worklog.sessions_at(entry.line).any(|s| s.company == *project) {
+                    continue;
+                }
+                billed_days.entry(day.date).or_insert_with(|| new_day(day.date)).expenses.push(BilledExpense {
+                    cost: expense.cost,
+                    what: action_text(&expense.what, policy.tags),
+                });
+                lines.push(entry.line);
+            }
         }
-        println!("</td>");
-        println!("</tr>");
     }
 
-    println!("</table>");
-    println!();
+    lines.sort();
+    lines.dedup();
 
-    if total_expenses > 0.0 {
-        println!("<h2>Expenses</h2>");
-        println!();
-        println!("<table>");
-        println!("<tr><th>Date</th><th>Cost</th><th>Detail</th></tr>");
+    Billed { days: billed_days.into_values().collect(), lines, rounding: rounding.clone() }
+}
 
-        for expense in expenses {
-            println!("<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
-                     expense.date, expense.cost, expense.what);
+// An action or expense as shown on an invoice
+fn action_text(text: &str, display: TagDisplay) -> String {
+    match display {
+        TagDisplay::Keep => text.to_string(),
+        TagDisplay::Strip => {
+            let mut stripped = String::new();
+            let mut rest = 0;
+            for (span, _) in tag_spans(text) {
+                stripped.push_str(&text[rest .. span.start]);
+                rest = span.end;
+            }
+            stripped.push_str(&text[rest..]);
+            stripped.split_whitespace().collect::<Vec<_>>().join(" ")
         }
-
-        println!("</table>");
-        println!();
     }
-    
-    Ok(())
 }
 
-fn print_report_ds(start: NaiveDate, end: NaiveDate, rate: f64,
-                   data: &[(NaiveDate, Hours, Vec<Action>)],
-                   expenses: Vec<Expense>,
-                   self_name: String,
-                   client: Option<String>,
-                   invoice_no: Option<u32>,
-                   issue_date: Option<NaiveDate>, due_date: Option<NaiveDate>) -> Result<()> {
-
-    fn print_table_row_2(v1: impl Display, v2: impl Display) {
-        println!("<tr><td>{}</td><td>{}</td></tr>", v1, v2);
+type WallClock = (NaiveDate, Time, NaiveDate, Time);
+
+// When a session started and ended, in `tz` if it can be converted
+fn wall_clock(session: &Session, tz: Option<Tz>) -> WallClock {
+    let as_written = (session.date, session.start, session.end_date, session.end);
+    let tz = match tz {
+        Some(tz) => tz,
+        None => return as_written,
+    };
+    match (session.start_instant(), session.end_instant()) {
+        (Some(start), Some(end)) => {
+            let start = start.with_timezone(&tz);
+            let end = end.with_timezone(&tz);
+            (start.date_naive(), Time::new(start.hour() as u8, start.minute() as u8),
+             end.date_naive(), Time::new(end.hour() as u8, end.minute() as u8))
+        }
+        _ => as_written,
     }
+}
 
-    let total_hours = data.iter().fold(0.0, |sum, &(_, hours, _)| sum + hours);
-    let total_expenses = expenses.iter().fold(0.0, |total, expense| total + expense.cost);
-    let amount_due = rate * total_hours + total_expenses;
-
-    println!("<!doctype html>");
-    println!("<meta charset='utf-8'>");
-    println!("{}", STYLE);
-    println!("");
-    println!("<h1>Invoice from {}</h1>", self_name);
-    println!();
-    println!("<table>");
-    print_table_row_2("name:", self_name);
-    print_table_row_2("email:", "andersrb@gmail.com");
-    if let Some(client) = client {
-        print_table_row_2("client:", client);
+// The parts of a session on each day it spans, as (date, start, end,
+// minutes). Midnight ending a part is written "12:00 AM". Any hour
+// gained or lost to DST goes to the last part, so the parts add up to
+// `minutes`.
+fn split_at_midnight(clock: WallClock, minutes: Minutes) -> Vec<(NaiveDate, Time, Time, Minutes)> {
+    let (mut date, mut from, end_date, end) = clock;
+    let midnight = Time::new(0, 0);
+    let mut pieces = vec![];
+    while date < end_date {
+        pieces.push((date, from, midnight, MINUTES_PER_DAY - from.minute_of_day()));
+        date = date.succ_opt().expect("");
+        from = midnight;
     }
-    if let Some(invoice_no) = invoice_no {
-        print_table_row_2("invoice number:", invoice_no);
+    let before: Minutes = pieces.iter().map(|p| p.3).sum();
+    pieces.push((date, from, end, minutes.saturating_sub(before)));
+    pieces.retain(|p| p.3 > 0);
+    pieces
+}
+
+impl Default for Rounding {
+    fn default() -> Rounding {
+        // For Reddit at least I need reports accurate to the half-hour
+        Rounding {
+            increment: 30,
+            mode: RoundingMode::Nearest,
+            per: RoundingScope::Session,
+            minimum: 0,
+        }
     }
-    print_table_row_2("reporting period:", format!("{} - {}", start, end));
-    if let Some(issue_date) = issue_date {
-        print_table_row_2("issue date:", issue_date);
+}
+
+impl Rounding {
+    pub fn round(&self, minutes: Minutes) -> Hours {
+        let mut billed = minutes as f64;
+        if self.increment > 0 {
+            let increments = billed / self.increment as f64;
+            let increments = match self.mode {
+                RoundingMode::Nearest => increments.round(),
+                RoundingMode::Up => increments.ceil(),
+                RoundingMode::Down => increments.floor(),
+            };
+            billed = increments * self.increment as f64;
+        }
+        if minutes > 0 && billed < self.minimum as f64 {
+            billed = self.minimum as f64;
+        }
+        billed / 60.0
     }
-    if let Some(due_date) = due_date {
-        print_table_row_2("due date:", due_date);
+}
+
+// E.g. "rounded up to 15 minutes per day, at least 60 minutes"
+impl fmt::Display for Rounding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.increment == 0 {
+            write!(f, "exact")?;
+        } else {
+            let mode = match self.mode {
+                RoundingMode::Nearest => "to the nearest",
+                RoundingMode::Up => "up to",
+                RoundingMode::Down => "down to",
+            };
+            write!(f, "rounded {} {} minutes per {}", mode, self.increment, self.per)?;
+        }
+        if self.minimum > 0 {
+            write!(f, ", at least {} minutes", self.minimum)?;
+        }
+        Ok(())
     }
-    print_table_row_2("total hours:", format!("{:.1}", total_hours));
-    print_table_row_2("hourly rate:", format!("{:} USD", rate));
-    if total_expenses > 0.0 {
-        print_table_row_2("expenses:", format!("{:.2} USD", total_expenses));
+}
+
+impl fmt::Display for RoundingScope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            RoundingScope::Session => "session",
+            RoundingScope::Day => "day",
+            RoundingScope::Invoice => "invoice",
+        })
     }
-    print_table_row_2("amount due:", format!("{:.2} USD", amount_due));
-    println!("</table>");
-    println!();
-
-    println!("<h2>TL;DR</h2>");
-    println!();
-    println!("<p>");
-    println!("TODO fill-me-in");
-    println!("</p>");
-    println!();
-
-    println!("<h2>Details</h2>");
-    println!();
-    println!("<table>");
-    println!("<tr><th>Description</th><th>Hours</th><th>Rate</th><th>Cost</th></tr>");
-
-    for &(date, hours, ref actions) in data {
-        println!("<tr>");
-        println!("<td>");
-        println!("<p>{}</p>", date);
-        for action in actions {
-            let action = parse_md_link(action);
-            println!("<p>{}</p>", action);
+}
+
+impl FromStr for RoundingMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> ::std::result::Result<RoundingMode, String> {
+        match s {
+            "nearest" => Ok(RoundingMode::Nearest),
+            "up" => Ok(RoundingMode::Up),
+            "down" => Ok(RoundingMode::Down),
+            _ => Err(format!("expected nearest, up or down, not '{}'", s)),
         }
-        println!("</td>");
-        println!("<td>{:2.1}</td><td>{}</td><td>{}</td>",
-                 hours, rate, hours * rate);
-        println!("</tr>");
     }
+}
 
-    println!("</table>");
-    println!();
-
-    if total_expenses > 0.0 {
-        println!("<h2>Expenses</h2>");
-        println!();
-        println!("<table>");
-        println!("<tr><th>Date</th><th>Cost</th><th>Detail</th></tr>");
+impl FromStr for TagDisplay {
+    type Err = String;
 
-        for expense in expenses {
-            println!("<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
-                     expense.date, expense.cost, expense.what);
+    fn from_str(s: &str) -> ::std::result::Result<TagDisplay, String> {
+        match s {
+            "keep" => Ok(TagDisplay::Keep),
+            "strip" => Ok(TagDisplay::Strip),
+            _ => Err(format!("expected keep or strip, not '{}'", s)),
         }
+    }
+}
 
-        println!("</table>");
-        println!();
+impl FromStr for Midnight {
+    type Err = String;
+
+    fn from_str(s: &str) -> ::std::result::Result<Midnight, String> {
+        match s {
+            "start" => Ok(Midnight::Start),
+            "split" => Ok(Midnight::Split),
+            _ => Err(format!("expected start or split, not '{}'", s)),
+        }
     }
-    
-    Ok(())
 }
 
-static STYLE: &str =
-    "
-<style>
-* {
-  font-family: sans-serif;
-  line-height: 1.3em;
+impl FromStr for RoundingScope {
+    type Err = String;
+
+    fn from_str(s: &str) -> ::std::result::Result<RoundingScope, String> {
+        match s {
+            "session" => Ok(RoundingScope::Session),
+            "day" => Ok(RoundingScope::Day),
+            "invoice" => Ok(RoundingScope::Invoice),
+            _ => Err(format!("expected session, day or invoice, not '{}'", s)),
+        }
+    }
 }
 
-body {
-  padding: 1em;
+// Everything that goes on an invoice, for a `ReportRenderer`. This is
+// also what invoice templates see.
+#[derive(Debug, Clone, Serialize)]
+pub struct InvoiceReport {
+    pub self_name: String,
+    pub identity: Identity,
+    pub client: Option<String>,
+    pub client_address: Option<String>,
+    pub invoice_no: Option<String>,
+    #[serde(with = "::ledger::date_format")]
+    pub start: NaiveDate,
+    #[serde(with = "::ledger::date_format")]
+    pub end: NaiveDate,
+    #[serde(with = "::ledger::opt_date_format")]
+    pub issue_date: Option<NaiveDate>,
+    #[serde(with = "::ledger::opt_date_format")]
+    pub due_date: Option<NaiveDate>,
+    pub rate: f64,
+    pub currency: String,
+    pub notes: Option<String>,
+    // One per session, in `InvoiceOptions::order`
+    pub items: Vec<LineItem>,
+    // The same sessions grouped by day
+    pub days: Vec<ReportDay>,
+    pub expenses: Vec<ExpenseItem>,
+    // Billed, after rounding
+    pub total_hours: Hours,
+    // As clocked, less breaks
+    pub total_minutes: Minutes,
+    pub total_break_minutes: Minutes,
+    pub show_breaks: bool,
+    // Billed hours less clocked hours
+    pub rounding_adjustment: Hours,
+    // A description of the rounding policy, e.g. "rounded to the nearest
+    // 30 minutes per session"
+    pub rounding: String,
+    pub rounding_policy: Rounding,
+    // The zone times are shown in, if converted
+    pub timezone: Option<String>,
+    pub total_expenses: f64,
+    pub amount_due: f64,
 }
 
-table {
-  border-collapse: collapse;
+#[derive(Debug, Clone, Serialize)]
+pub struct LineItem {
+    #[serde(with = "::ledger::date_format")]
+    pub date: NaiveDate,
+    // As written in the worklog, e.g. "9:05 AM"; empty for a logged
+    // duration
+    pub start: String,
+    pub end: String,
+    pub minutes: Minutes,
+    pub break_minutes: Minutes,
+    pub hours: Hours,
+    pub actions: Vec<Action>,
+    pub tags: Vec<TagTime>,
 }
 
-th, td {
-  border: 1px solid black;
-  padding: 0.2em 1em 0.2em 1em;
-  vertical-align: top;
+// Time spent on a tag within a session, as clocked
+#[derive(Debug, Clone, Serialize)]
+pub struct TagTime {
+    pub tag: String,
+    pub minutes: Minutes,
 }
 
-td p {
-  margin: 0;
+#[derive(Debug, Clone, Serialize)]
+pub struct ReportDay {
+    #[serde(with = "::ledger::date_format")]
+    pub date: NaiveDate,
+    // As clocked, and billed
+    pub minutes: Minutes,
+    pub hours: Hours,
+    // The actions of all the day's sessions
+    pub actions: Vec<Action>,
 }
 
-a, a:visited {
-  color: blue;
+#[derive(Debug, Clone, Serialize)]
+pub struct ExpenseItem {
+    #[serde(with = "::ledger::date_format")]
+    pub date: NaiveDate,
+    pub cost: f64,
+    pub what: String,
 }
-</style>";
 
-fn parse_md_link(text: &str) -> String {
-    lazy_static! {
-        static ref REGEX: Regex = Regex::new(r"^(.*)\[(.*)\]\((.*)\)(.*)$").unwrap();
-    }
+impl InvoiceReport {
+    pub fn new(opts: &InvoiceOptions, billed: &Billed) -> InvoiceReport {
+        let mut items = vec![];
+        let mut days = vec![];
+        let mut expenses = vec![];
+
+        let ordered: Box<dyn Iterator<Item = &BilledDay>> = match opts.order {
+            DayOrder::OldestFirst => Box::new(billed.days.iter()),
+            DayOrder::NewestFirst => Box::new(billed.days.iter().rev()),
+        };
+        for day in ordered {
+            if !day.sessions.is_empty() {
+                days.push(ReportDay {
+                    date: day.date,
+                    minutes: day.minutes(),
+                    hours: billed.day_hours(day),
+                    actions: day.sessions.iter().flat_map(|s| s.actions.iter().cloned()).collect(),
+                });
+            }
+            for session in &day.sessions {
+                items.push(LineItem {
+                    date: day.date,
+                    start: session.start.clone(),
+                    end: session.end.clone(),
+                    minutes: session.minutes,
+                    break_minutes: session.break_minutes,
+                    hours: session.hours,
+                    actions: session.actions.clone(),
+                    tags: session.tags.iter().map(|(tag, &minutes)| TagTime { tag: tag.clone(), minutes }).collect(),
+                });
+            }
+            for expense in &day.expenses {
+                expenses.push(ExpenseItem { date: day.date, cost: expense.cost, what: expense.what.clone() });
+            }
+        }
 
-    let caps = REGEX.captures(text);
-    if let Some(caps) = caps {
-        let pre = &caps[1];
-        let text = &caps[2];
-        let link = &caps[3];
-        let post = &caps[4];
-        format!("{}<a href='{}'>{}</a>{}",
-                pre, link, text, post)
-    } else {
-        text.to_string()
+        let total_break_minutes = items.iter().map(|i| i.break_minutes).sum();
+
+        InvoiceReport {
+            self_name: opts.self_name.clone(),
+            identity: opts.identity.clone(),
+            client: opts.client.clone(),
+            client_address: opts.client_address.clone(),
+            invoice_no: opts.invoice_no.clone(),
+            start: opts.start,
+            end: opts.end,
+            issue_date: opts.issue_date,
+            due_date: opts.due_date,
+            rate: opts.rate,
+            currency: opts.currency.clone(),
+            notes: opts.notes.clone(),
+            items,
+            days,
+            expenses,
+            total_hours: billed.hours(),
+            total_minutes: billed.minutes(),
+            total_break_minutes,
+            show_breaks: opts.show_breaks,
+            rounding_adjustment: billed.adjustment(),
+            rounding: billed.rounding.to_string(),
+            rounding_policy: billed.rounding.clone(),
+            timezone: opts.policy.timezone.map(|tz| tz.name().to_string()),
+            total_expenses: billed.expenses(),
+            amount_due: billed.amount_due(opts.rate),
+        }
     }
 }
+
+pub fn do_time_report(worklog: &Worklog, opts: &InvoiceOptions, out: &mut dyn Write) -> Result<Billed> {
+    let renderer = render::renderer(opts)?;
+    let billed = bill(worklog, &opts.project, opts.start, opts.end, &opts.policy);
+    let report = InvoiceReport::new(opts, &billed);
+
+    renderer.render(&report, out)?;
+
+    Ok(billed)
+}
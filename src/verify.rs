@@ -0,0 +1,134 @@
+// Checking issued invoices against the current worklog.
+//
+// Every invoice in the ledger is billed again from the worklog as it is
+// now, and compared day by day with what was recorded when it was
+// issued.
+
+use std::fmt;
+use chrono::NaiveDate;
+
+use ledger::{self, Ledger, LedgerEntry};
+use model::Worklog;
+use time_reporting::{self as tr, BilledDay};
+
+pub struct InvoiceChanges<'a> {
+    pub invoice: &'a LedgerEntry,
+    pub days: Vec<DayChanges>,
+    // The billed lines changed, even if nothing billed from them did
+    pub hash_changed: bool,
+}
+
+pub struct DayChanges {
+    pub date: NaiveDate,
+    // Lines prefixed with '-' for billed, '+' for now, or ' ' for both
+    pub diff: Vec<(char, String)>,
+}
+
+// Invoices that would be billed differently today. `lines` is the
+// worklog source, for comparing hashes.
+pub fn verify<'a>(worklog: &Worklog, lines: &[&str], ledger: &'a Ledger) -> Vec<InvoiceChanges<'a>> {
+    ledger.invoices.iter().filter_map(|invoice| {
+        let billed = tr::bill(worklog, &invoice.project, invoice.start, invoice.end, &invoice.policy());
+        let hash = ledger::hash_lines(billed.lines.iter().filter_map(|&l| lines.get(l - 1).cloned()));
+
+        let mut dates: Vec<NaiveDate> = invoice.days.iter().chain(billed.days.iter())
+            .map(|d| d.date).collect();
+        dates.sort();
+        dates.dedup();
+
+        let days: Vec<DayChanges> = dates.into_iter().filter_map(|date| {
+            let was = invoice.days.iter().find(|d| d.date == date);
+            let now = billed.days.iter().find(|d| d.date == date);
+            if was == now {
+                return None;
+            }
+            let diff = diff_lines(&describe_day(was), &describe_day(now));
+            Some(DayChanges { date, diff })
+        }).collect();
+
+        let hash_changed = hash != invoice.hash;
+
+        if days.is_empty() && !hash_changed {
+            None
+        } else {
+            Some(InvoiceChanges { invoice, days, hash_changed })
+        }
+    }).collect()
+}
+
+fn describe_day(day: Option<&BilledDay>) -> Vec<String> {
+    let mut lines = vec![];
+    if let Some(day) = day {
+        for session in &day.sessions {
+            if session.start.is_empty() {
+                lines.push(format!("duration {} minutes, {:.1} hours", session.minutes, session.hours));
+            } else {
+                lines.push(format!("session {} - {}, {} minutes, {:.1} hours",
+                                   session.start, session.end, session.minutes, session.hours));
+                if session.break_minutes > 0 {
+                    lines.push(format!("    less {} minutes of breaks", session.break_minutes));
+                }
+            }
+            for action in &session.actions {
+                lines.push(format!("    {}", action));
+            }
+        }
+        for expense in &day.expenses {
+            lines.push(format!("expense ${:.2},{}", expense.cost, expense.what));
+        }
+    }
+    lines
+}
+
+// A minimal line diff via the longest common subsequence
+fn diff_lines(old: &[String], new: &[String]) -> Vec<(char, String)> {
+    let (n, m) = (old.len(), new.len());
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old[i] == new[j] {
+            diff.push((' ', old[i].clone()));
+            i += 1;
+            j += 1;
+        } else if i < n && (j == m || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            diff.push(('-', old[i].clone()));
+            i += 1;
+        } else {
+            diff.push(('+', new[j].clone()));
+            j += 1;
+        }
+    }
+    diff
+}
+
+impl<'a> fmt::Display for InvoiceChanges<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let invoice = self.invoice;
+        writeln!(f, "invoice {} ({}, {} - {}) has changed",
+                 invoice.number, invoice.project.as_deref().unwrap_or("no project"),
+                 invoice.start, invoice.end)?;
+        if self.days.is_empty() && invoice.days.is_empty() {
+            writeln!(f, "  the billed worklog lines differ; the ledger has no day-by-day record to compare")?;
+        } else if self.days.is_empty() {
+            writeln!(f, "  the billed worklog lines differ, but bill the same")?;
+        }
+        for day in &self.days {
+            writeln!(f, "  {}", day.date)?;
+            for &(mark, ref line) in &day.diff {
+                writeln!(f, "    {} {}", mark, line)?;
+            }
+        }
+        Ok(())
+    }
+}
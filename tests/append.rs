@@ -0,0 +1,145 @@
+extern crate chrono;
+extern crate worklog_scan;
+
+use chrono::NaiveDate;
+use worklog_scan::*;
+use worklog_scan::append::*;
+
+fn at(date: &str, hour: u32, minute: u32) -> FixedTime {
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap();
+    FixedTime(date.and_hms_opt(hour, minute, 0).unwrap())
+}
+
+fn add(text: &str, entry: NewEntry, clock: &FixedTime) -> Result<String, String> {
+    add_with(text, entry, clock, &ParseOptions::default())
+}
+
+fn add_with(text: &str, entry: NewEntry, clock: &FixedTime, opts: &ParseOptions) -> Result<String, String> {
+    let mut sources = Sources::new();
+    sources.add("worklog.md", text.to_string());
+    add_entry(&sources, opts, &entry, clock).map(|a| a.text).map_err(|e| e.to_string())
+}
+
+fn nervos() -> Option<String> {
+    Some("Nervos".to_string())
+}
+
+#[test]
+fn new_day_goes_on_top_when_newest_first() {
+    let text = "# Worklog\n\n# 2021-02-02\n- b\n# 2021-02-01\n- a\n";
+    let out = add(text, NewEntry::ClockIn(nervos()), &at("2021-02-03", 9, 3)).unwrap();
+    assert_eq!(out, "# Worklog\n\n# 2021-02-03\n- clock in (Nervos)\n- 9:03 AM\n\
+                     # 2021-02-02\n- b\n# 2021-02-01\n- a\n");
+}
+
+#[test]
+fn new_day_goes_at_the_bottom_when_oldest_first() {
+    let text = "# 2021-02-01\n- a\n\n# 2021-02-02\n- b\n\n";
+    let out = add(text, NewEntry::Note("c".to_string()), &at("2021-02-03", 14, 0)).unwrap();
+    assert_eq!(out, "# 2021-02-01\n- a\n\n# 2021-02-02\n- b\n\n# 2021-02-03\n- c\n\n");
+}
+
+#[test]
+fn entries_go_at_the_end_of_today() {
+    let text = "# 2021-02-03\n- clock in (Nervos)\n- 9:00 AM\n\n# 2021-02-02\n- b\n";
+    let out = add(text, NewEntry::Expense(12.5, "parking".to_string()), &at("2021-02-03", 10, 0)).unwrap();
+    assert_eq!(out, "# 2021-02-03\n- clock in (Nervos)\n- 9:00 AM\n- Expense: $12.50, parking\n\n\
+                     # 2021-02-02\n- b\n");
+}
+
+#[test]
+fn empty_worklog() {
+    let out = add("", NewEntry::Note("started".to_string()), &at("2021-02-03", 9, 0)).unwrap();
+    assert_eq!(out, "# 2021-02-03\n- started\n");
+}
+
+#[test]
+fn refuses_to_clock_in_twice() {
+    let text = "# 2021-02-03\n- clock in (Nervos)\n- 9:00 AM\n";
+    let err = add(text, NewEntry::ClockIn(nervos()), &at("2021-02-03", 10, 0)).unwrap_err();
+    assert_eq!(err, "already clocked in for 'Nervos' at worklog.md:2");
+
+    // Another company is fine
+    assert!(add(text, NewEntry::ClockIn(Some("Other".to_string())), &at("2021-02-03", 10, 0)).is_ok());
+}
+
+#[test]
+fn clocking_out_names_the_open_session() {
+    let text = "# 2021-02-03\n- clock in (Nervos)\n- 9:00 AM\n";
+    let out = add(text, NewEntry::ClockOut(None), &at("2021-02-03", 17, 30)).unwrap();
+    assert_eq!(out, "# 2021-02-03\n- clock in (Nervos)\n- 9:00 AM\n- 5:30 PM\n- clock out (Nervos)\n");
+
+    let err = add(&out, NewEntry::ClockOut(None), &at("2021-02-03", 18, 0)).unwrap_err();
+    assert_eq!(err, "not clocked in");
+    let err = add(text, NewEntry::ClockOut(Some("Other".to_string())), &at("2021-02-03", 18, 0)).unwrap_err();
+    assert_eq!(err, "not clocked in for 'Other'");
+}
+
+#[test]
+fn clocking_out_of_two_sessions_needs_a_company() {
+    let text = "# 2021-02-03\n- clock in (Nervos)\n- 9:00 AM\n- clock in (Other)\n- 10:00 AM\n";
+    let err = add(text, NewEntry::ClockOut(None), &at("2021-02-03", 17, 0)).unwrap_err();
+    assert_eq!(err, "clocked in for Nervos, Other; name the company");
+
+    let out = add(text, NewEntry::ClockOut(Some("Other".to_string())), &at("2021-02-03", 17, 0)).unwrap();
+    let out = add(&out, NewEntry::ClockOut(None), &at("2021-02-03", 17, 30)).unwrap();
+    assert!(out.ends_with("- 5:30 PM\n- clock out (Nervos)\n"));
+    let worklog = parse(&out).unwrap();
+    assert_eq!(worklog.sessions().count(), 2);
+}
+
+#[test]
+fn clocking_out_past_midnight() {
+    let text = "# 2021-02-03\n- clock in (Nervos)\n- 10:00 PM\n";
+    let out = add(text, NewEntry::ClockOut(nervos()), &at("2021-02-04", 1, 0)).unwrap();
+    let worklog = parse(&out).unwrap();
+    let sessions: Vec<&Session> = worklog.sessions().collect();
+    assert_eq!(sessions.len(), 1);
+    assert_eq!(sessions[0].minutes(), 180);
+}
+
+#[test]
+fn twenty_four_hour_worklog() {
+    let opts = ParseOptions { time_formats: vec![TimeFormat::TwentyFourHour], ..ParseOptions::default() };
+    let out = add_with("", NewEntry::ClockIn(nervos()), &at("2021-02-03", 9, 3), &opts).unwrap();
+    assert_eq!(out, "# 2021-02-03\n- clock in (Nervos)\n- 09:03\n");
+}
+
+#[test]
+fn a_day_clocked_with_commands_bills() {
+    let mut text = String::new();
+    let steps = vec![
+        (NewEntry::ClockIn(nervos()), at("2021-02-03", 9, 0)),
+        (NewEntry::Note("fixed the build".to_string()), at("2021-02-03", 10, 0)),
+        (NewEntry::ClockOut(nervos()), at("2021-02-03", 11, 30)),
+    ];
+    for (entry, clock) in steps {
+        text = add(&text, entry, &clock).unwrap();
+    }
+    let worklog = parse(&text).unwrap();
+    let session = worklog.sessions().next().unwrap();
+    assert_eq!(session.minutes(), 150);
+    assert_eq!(session.actions[0].text, "fixed the build");
+}
+
+#[test]
+fn refuses_notes_read_as_something_else() {
+    let clock = at("2021-02-03", 10, 0);
+    let err = add("", NewEntry::Note("discussed the clock in process".to_string()), &clock).unwrap_err();
+    assert_eq!(err, "'discussed the clock in process' would not be read as a note");
+    let err = add("", NewEntry::Note("a\n# 2020-01-01".to_string()), &clock).unwrap_err();
+    assert_eq!(err, "an entry must be one line");
+    let err = add("", NewEntry::Note("9:00 AM".to_string()), &clock).unwrap_err();
+    assert_eq!(err, "'9:00 AM' would not be read as a note");
+}
+
+#[test]
+fn refuses_expenses_that_would_not_parse() {
+    let clock = at("2021-02-03", 10, 0);
+    let err = add("", NewEntry::Expense(f64::INFINITY, "parking".to_string()), &clock).unwrap_err();
+    assert_eq!(err, "an expense must be a cost of 0 or more, not inf");
+    assert!(add("", NewEntry::Expense(f64::NAN, "parking".to_string()), &clock).is_err());
+    assert!(add("", NewEntry::Expense(-1.0, "parking".to_string()), &clock).is_err());
+    let err = add("", NewEntry::Expense(5.0, "parking\n- clock in".to_string()), &clock).unwrap_err();
+    assert_eq!(err, "an entry must be one line");
+}
@@ -0,0 +1,65 @@
+extern crate worklog_scan;
+
+use worklog_scan::*;
+
+fn times(text: &str, formats: &[TimeFormat]) -> (Vec<Time>, usize) {
+    let text = format!("# 2021-02-01\n{}", text);
+    let opts = ParseOptions { time_formats: formats.to_vec(), ..ParseOptions::default() };
+    let (worklog, diags) = parse_worklog_with("<test>", &text, &opts);
+    let times = worklog.days[0].entries.iter().filter_map(|e| {
+        match e.kind {
+            EntryKind::Time(t) => Some(t),
+            _ => None,
+        }
+    }).collect();
+    (times, diags.errors().count())
+}
+
+const BOTH: &[TimeFormat] = &[TimeFormat::TwelveHour, TimeFormat::TwentyFourHour];
+
+#[test]
+fn midnight_and_noon_12_hour() {
+    let (times, errors) = times("- 12:00 AM\n- 12:59 am\n- 12:00 PM\n- 12:30 p.m.\n- 11:59 PM\n", BOTH);
+    assert_eq!(errors, 0);
+    assert_eq!(times, vec![Time::new(0, 0), Time::new(0, 59), Time::new(12, 0),
+                           Time::new(12, 30), Time::new(23, 59)]);
+}
+
+#[test]
+fn midnight_and_noon_24_hour() {
+    let (times, errors) = times("- 00:00\n- 0:30\n- 12:00\n- 23:59\n", BOTH);
+    assert_eq!(errors, 0);
+    assert_eq!(times, vec![Time::new(0, 0), Time::new(0, 30), Time::new(12, 0), Time::new(23, 59)]);
+}
+
+#[test]
+fn flexible_12_hour() {
+    let (times, errors) = times("- 9:30am\n- 9:30 A.M.\n- 9:30:45 PM\n- 1:05 pm lunch\n", BOTH);
+    assert_eq!(errors, 0);
+    assert_eq!(times, vec![Time::new(9, 30), Time::new(9, 30), Time::new(21, 30), Time::new(13, 5)]);
+}
+
+#[test]
+fn out_of_range() {
+    assert_eq!(times("- 13:00 PM\n", BOTH), (vec![], 1));
+    assert_eq!(times("- 24:00\n", BOTH), (vec![], 1));
+    assert_eq!(times("- 9:60 AM\n", BOTH), (vec![], 1));
+    assert_eq!(times("- 9:30:60\n", BOTH), (vec![], 1));
+}
+
+#[test]
+fn formats_are_configurable() {
+    let twelve = &[TimeFormat::TwelveHour];
+    let twenty_four = &[TimeFormat::TwentyFourHour];
+    assert_eq!(times("- 10:30 standup\n", twelve), (vec![], 0));
+    assert_eq!(times("- 10:30 standup\n", twenty_four), (vec![Time::new(10, 30)], 0));
+    // Never misread as 9:30 in the morning
+    assert_eq!(times("- 9:30 PM\n", twenty_four), (vec![], 0));
+}
+
+#[test]
+fn ratios_are_not_times() {
+    let (times, errors) = times("- 3:2 odds\n- 5/3:4/2\n- 10:30/11:00 split\n", BOTH);
+    assert_eq!(errors, 0);
+    assert!(times.is_empty());
+}